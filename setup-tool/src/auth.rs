@@ -0,0 +1,79 @@
+//! Resolves and stores the credentials `setup-tool` needs (Fastly API token, StegaWave API
+//! key) without ever putting them in `config.toml` in plaintext. Resolution order mirrors
+//! the SOTA client's `auth`/`oauth2` modules: an explicit CLI value, then the OS keychain,
+//! then the matching environment variable, and only then an interactive prompt.
+
+use dialoguer::Password;
+use keyring::Entry;
+use secrecy::{ExposeSecret, SecretString};
+
+const KEYRING_SERVICE: &str = "stegawave";
+
+/// The credentials `setup-tool` manages in the keychain, one keychain entry per profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential {
+    FastlyToken,
+    StegawaveApiKey,
+}
+
+impl Credential {
+    fn account(self, profile: &str) -> String {
+        match self {
+            Credential::FastlyToken => format!("{}:fastly-token", profile),
+            Credential::StegawaveApiKey => format!("{}:stegawave-api-key", profile),
+        }
+    }
+
+    fn env_var(self) -> &'static str {
+        match self {
+            Credential::FastlyToken => "FASTLY_API_TOKEN",
+            Credential::StegawaveApiKey => "STEGAWAVE_API_KEY",
+        }
+    }
+
+    fn prompt(self) -> &'static str {
+        match self {
+            Credential::FastlyToken => "Enter your Fastly API token",
+            Credential::StegawaveApiKey => "Enter your StegaWave API key",
+        }
+    }
+}
+
+fn entry(cred: Credential, profile: &str) -> Result<Entry, Box<dyn std::error::Error>> {
+    Ok(Entry::new(KEYRING_SERVICE, &cred.account(profile))?)
+}
+
+/// Checks the keychain and then the environment for `cred`, without prompting. Used where a
+/// missing credential has a sensible non-interactive fallback (e.g. `dev`'s dummy API key).
+pub fn peek(cred: Credential, profile: &str) -> Option<SecretString> {
+    if let Ok(stored) = entry(cred, profile).ok()?.get_password() {
+        return Some(SecretString::new(stored));
+    }
+    std::env::var(cred.env_var()).ok().filter(|v| !v.is_empty()).map(SecretString::new)
+}
+
+/// Resolves `cred` for `profile`: `explicit` (e.g. a `--fastly-token` flag) first, then
+/// `peek`, and only then an interactive prompt.
+pub fn resolve(explicit: Option<SecretString>, cred: Credential, profile: &str) -> Result<SecretString, Box<dyn std::error::Error>> {
+    if let Some(value) = explicit {
+        return Ok(value);
+    }
+    if let Some(stored) = peek(cred, profile) {
+        return Ok(stored);
+    }
+    Ok(SecretString::new(Password::new().with_prompt(cred.prompt()).interact()?))
+}
+
+/// Stores `value` for `cred` under `profile` in the OS keychain.
+pub fn store(cred: Credential, profile: &str, value: &SecretString) -> Result<(), Box<dyn std::error::Error>> {
+    entry(cred, profile)?.set_password(value.expose_secret())?;
+    Ok(())
+}
+
+/// Clears any stored value for `cred` under `profile`. A missing entry isn't an error.
+pub fn clear(cred: Credential, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match entry(cred, profile)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
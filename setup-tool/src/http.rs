@@ -0,0 +1,108 @@
+//! Talks to `https://api.fastly.com` directly instead of shelling out to the `fastly` CLI.
+//!
+//! Used when `--use-api` is passed to `install`/`update`, so the KV store can be
+//! provisioned and populated in CI containers that only have an API token and no
+//! `fastly` binary on `PATH`. `compute build`/`deploy` still need the CLI toolchain and
+//! are unaffected.
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+
+const API_BASE: &str = "https://api.fastly.com";
+
+#[derive(Debug, Deserialize)]
+struct KvStore {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvStoreList {
+    data: Vec<KvStore>,
+}
+
+/// `POST /resources/stores/kv`, treating HTTP 409 as the idempotent "already exists" case.
+pub async fn create_kv_store(client: &Client, name: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Creating KV store: {}", name);
+
+    let response = client
+        .post(format!("{}/resources/stores/kv", API_BASE))
+        .header("Fastly-Key", token)
+        .json(&serde_json::json!({ "name": name }))
+        .send()
+        .await?;
+
+    match response.status() {
+        StatusCode::CONFLICT => {
+            println!("✓ KV store '{}' already exists", name);
+            Ok(())
+        }
+        status if status.is_success() => {
+            println!("✓ Created KV store: {}", name);
+            Ok(())
+        }
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            Err(format!("Failed to create KV store '{}': {} {}", name, status, body).into())
+        }
+    }
+}
+
+/// `GET /resources/stores/kv?name=`, returning the store's id.
+pub async fn get_kv_store_id(client: &Client, name: &str, token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let response = client
+        .get(format!("{}/resources/stores/kv", API_BASE))
+        .header("Fastly-Key", token)
+        .query(&[("name", name)])
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to describe KV store '{}': {} {}", name, status, body).into());
+    }
+
+    let list: KvStoreList = response.json().await?;
+    list.data.into_iter().next()
+        .map(|store| store.id)
+        .ok_or_else(|| format!("KV store '{}' not found", name).into())
+}
+
+/// `GET /resources/stores/kv/{id}/keys/{key}`, used to skip no-op writes the same way the
+/// CLI path does. A missing key isn't an error — it just means nothing's been written yet.
+pub async fn get_kv_store_entry_value(client: &Client, store_id: &str, key: &str, token: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let response = client
+        .get(format!("{}/resources/stores/kv/{}/keys/{}", API_BASE, store_id, key))
+        .header("Fastly-Key", token)
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(response.text().await?))
+}
+
+/// `PUT /resources/stores/kv/{id}/keys/{key}`.
+pub async fn populate_kv_store_entry(client: &Client, store_id: &str, key: &str, value: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Setting KV store entry: {} = {}", key, if key.contains("SECRET") { "[REDACTED]" } else { value });
+
+    let response = client
+        .put(format!("{}/resources/stores/kv/{}/keys/{}", API_BASE, store_id, key))
+        .header("Fastly-Key", token)
+        .body(value.to_string())
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to create KV store entry '{}': {} {}", key, status, body).into());
+    }
+
+    println!("✓ Successfully set KV store entry: {}", key);
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! Parses and filters the JSON records from `fastly log-tail --format json`, routing them
+//! through `tracing` instead of `println!` so operators get leveled, filterable output
+//! (mirroring the `log` -> `tracing` migration the 2b-rs project did) instead of a raw
+//! firehose of text.
+
+use console::style;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// One record from `fastly log-tail --format json`. The exact field set depends on the
+/// service's own log lines, so everything beyond the handful of well-known keys is kept in
+/// `fields` rather than discarded.
+#[derive(Deserialize, Debug)]
+pub struct LogRecord {
+    #[serde(default)]
+    pub level: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(flatten)]
+    pub fields: HashMap<String, Value>,
+}
+
+/// Severity ordering for `--level`, matching the usual `tracing` levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(raw: &str) -> Level {
+        match raw.to_ascii_lowercase().as_str() {
+            "error" => Level::Error,
+            "warn" | "warning" => Level::Warn,
+            "info" => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+/// Client-side filter applied to each parsed record before it's emitted.
+pub struct Filter {
+    min_level: Level,
+    grep: Option<Regex>,
+    request_id: Option<String>,
+}
+
+impl Filter {
+    pub fn new(min_level: &str, grep: Option<&str>, request_id: Option<String>) -> Result<Filter, Box<dyn std::error::Error>> {
+        Ok(Filter {
+            min_level: Level::parse(min_level),
+            grep: grep.map(Regex::new).transpose()?,
+            request_id,
+        })
+    }
+
+    pub fn matches(&self, record: &LogRecord, raw: &str) -> bool {
+        let level = record.level.as_deref().map(Level::parse).unwrap_or(Level::Info);
+        if level < self.min_level {
+            return false;
+        }
+        if let Some(wanted) = &self.request_id {
+            if record.request_id.as_deref() != Some(wanted.as_str()) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.grep {
+            if !pattern.is_match(raw) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Accumulates raw log-tail lines until they form a complete JSON object, so records split
+/// across interleaved writes aren't dropped as parse failures. Gives up and resets after a
+/// few lines so a genuinely malformed stream can't grow this without bound.
+#[derive(Default)]
+pub struct PartialLineBuffer {
+    pending: String,
+    pending_lines: usize,
+}
+
+impl PartialLineBuffer {
+    /// Feeds one more raw line in; returns the parsed record (and the full raw JSON text) once
+    /// the buffered text parses, or `None` while still waiting on more lines.
+    pub fn push(&mut self, line: &str) -> Option<(LogRecord, String)> {
+        if !self.pending.is_empty() {
+            self.pending.push('\n');
+        }
+        self.pending.push_str(line);
+        self.pending_lines += 1;
+
+        match serde_json::from_str::<LogRecord>(&self.pending) {
+            Ok(record) => {
+                let raw = std::mem::take(&mut self.pending);
+                self.pending_lines = 0;
+                Some((record, raw))
+            }
+            Err(_) if self.pending_lines >= 10 => {
+                // Not valid JSON even after buffering several lines — drop it and resync.
+                self.pending.clear();
+                self.pending_lines = 0;
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// Colors watermarking-relevant keywords so they stand out in an otherwise plain log line.
+fn highlight(text: &str) -> String {
+    if text.contains("WATERMARK") || text.contains("FMP4") {
+        style(text).yellow().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Emits `record` through `tracing` at its own level, after filtering and highlighting.
+pub fn emit(record: &LogRecord, raw: &str) {
+    let message = record.message.as_deref().unwrap_or(raw);
+    let rendered = highlight(message);
+    match record.level.as_deref().map(Level::parse).unwrap_or(Level::Info) {
+        Level::Error => tracing::error!(request_id = record.request_id.as_deref().unwrap_or(""), "{}", rendered),
+        Level::Warn => tracing::warn!(request_id = record.request_id.as_deref().unwrap_or(""), "{}", rendered),
+        Level::Debug => tracing::debug!(request_id = record.request_id.as_deref().unwrap_or(""), "{}", rendered),
+        Level::Info => tracing::info!(request_id = record.request_id.as_deref().unwrap_or(""), "{}", rendered),
+    }
+}
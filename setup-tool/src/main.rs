@@ -1,19 +1,35 @@
 use clap::{Parser, Subcommand};
 use dialoguer::{Input, Password, Confirm};
 use reqwest::Client;
-use serde::Deserialize;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::process::{Command, Stdio};
 use std::fs;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::BufRead;
 use toml::Value;
 use console::style;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Config as RustylineConfig, CompletionType, EditMode, Editor, ExternalPrinter, Helper};
+use sha2::{Digest, Sha256};
+
+mod auth;
+mod http;
+mod log_tail;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Named profile from config.toml to read/write service_id and KV values for
+    #[arg(long, global = true, default_value = "default")]
+    profile: String,
 }
 
 #[derive(Subcommand, Debug)]
@@ -26,35 +42,185 @@ enum Commands {
     Deploy(DeployArgs),
     /// Tail the logs for the deployed service
     Tail(TailArgs),
+    /// Run the Compute service locally under Viceroy with emulated stores
+    Dev(DevArgs),
+    /// Show the deployed service and its provisioned store contents
+    Status(StatusArgs),
+    /// Get, set, or list individual watermarking_config keys
+    Config(ConfigArgs),
+    /// Compare live KV/secret store values against config.toml and report drift
+    Diff(DiffArgs),
+    /// Drop into an interactive REPL for repeated install/update/config operations
+    Shell(ShellArgs),
+    /// Store Fastly/StegaWave credentials in the OS keychain for the active profile
+    Login(LoginArgs),
+    /// Clear stored credentials for the active profile
+    Logout,
+}
+
+#[derive(Parser, Debug)]
+struct LoginArgs {
+    /// Your Fastly API token
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
+
+    /// Your StegaWave API key
+    #[arg(long, value_parser = parse_secret_string)]
+    stegawave_api_key: Option<SecretString>,
 }
 
 #[derive(Parser, Debug)]
 struct InstallArgs {
     /// Your Fastly API token
-    #[arg(long)]
-    fastly_token: Option<String>,
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
 
     /// Your StegaWave API key
+    #[arg(long, value_parser = parse_secret_string)]
+    stegawave_api_key: Option<SecretString>,
+
+    /// Path to a stegawave.toml manifest declaring watermarking config non-interactively
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Named `[[environments]]` block within --config to layer on top of `[watermarking]`
+    #[arg(long)]
+    environment: Option<String>,
+
+    /// Provision and populate the KV store over the Fastly REST API instead of the `fastly`
+    /// CLI (still used for `compute build`/`deploy`), for CI containers without the binary
     #[arg(long)]
-    stegawave_api_key: Option<String>,
+    use_api: bool,
+}
+
+/// The `[watermarking]` table of a `stegawave.toml` manifest.
+#[derive(Deserialize, Debug, Default, Clone)]
+struct WatermarkingManifest {
+    aac_profile: Option<String>,
+    sample_rate: Option<u32>,
+    channels: Option<u8>,
+    track_id: Option<u32>,
+}
+
+/// One entry of an optional `[[environments]]` array, layering overrides onto `[watermarking]`.
+#[derive(Deserialize, Debug, Clone)]
+struct EnvironmentManifest {
+    name: String,
+    #[serde(flatten)]
+    watermarking: WatermarkingManifest,
+}
+
+/// Top-level shape of a `stegawave.toml` manifest, used to drive `install` non-interactively.
+#[derive(Deserialize, Debug, Default)]
+struct StegawaveManifest {
+    watermarking: Option<WatermarkingManifest>,
+    environments: Option<Vec<EnvironmentManifest>>,
+}
+
+const VALID_SAMPLE_RATES: &[u32] = &[8000, 16000, 22050, 24000, 32000, 44100, 48000, 96000];
+
+/// Validates a manifest's watermarking parameters before any deploy runs.
+fn validate_watermarking(watermarking: &WatermarkingManifest) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(rate) = watermarking.sample_rate {
+        if !VALID_SAMPLE_RATES.contains(&rate) {
+            return Err(format!(
+                "Invalid FMP4_SAMPLE_RATE {} Hz; must be one of {:?}",
+                rate, VALID_SAMPLE_RATES
+            ).into());
+        }
+    }
+    if let Some(channels) = watermarking.channels {
+        if !(1..=8).contains(&channels) {
+            return Err(format!(
+                "Invalid FMP4_CHANNELS {}; must be between 1 and 8",
+                channels
+            ).into());
+        }
+    }
+    Ok(())
+}
+
+/// Loads and parses a `stegawave.toml` manifest from disk.
+fn load_manifest(path: &str) -> Result<StegawaveManifest, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read manifest '{}': {}", path, e))?;
+    let manifest: StegawaveManifest = toml::from_str(&content)
+        .map_err(|e| format!("Failed to parse manifest '{}': {}", path, e))?;
+    Ok(manifest)
+}
+
+/// Copies the fields a manifest's `[watermarking]` table (or an environment override) sets
+/// into `config`, returning which `FMP4_*` keys it touched.
+fn apply_manifest_watermarking(watermarking: &WatermarkingManifest, config: &mut HashMap<String, String>) -> HashSet<String> {
+    let mut set_keys = HashSet::new();
+    if let Some(v) = &watermarking.aac_profile {
+        config.insert("FMP4_AAC_PROFILE".to_string(), v.clone());
+        set_keys.insert("FMP4_AAC_PROFILE".to_string());
+    }
+    if let Some(v) = watermarking.sample_rate {
+        config.insert("FMP4_SAMPLE_RATE".to_string(), v.to_string());
+        set_keys.insert("FMP4_SAMPLE_RATE".to_string());
+    }
+    if let Some(v) = watermarking.channels {
+        config.insert("FMP4_CHANNELS".to_string(), v.to_string());
+        set_keys.insert("FMP4_CHANNELS".to_string());
+    }
+    if let Some(v) = watermarking.track_id {
+        config.insert("FMP4_TRACK_ID".to_string(), v.to_string());
+        set_keys.insert("FMP4_TRACK_ID".to_string());
+    }
+    set_keys
+}
+
+/// Prompts only for the `FMP4_*` keys not already supplied by a manifest.
+fn prompt_for_missing_config_values(config: &mut HashMap<String, String>, already_set: &HashSet<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let prompts = [
+        ("FMP4_AAC_PROFILE", "AAC Profile"),
+        ("FMP4_SAMPLE_RATE", "Sample Rate (Hz)"),
+        ("FMP4_CHANNELS", "Number of Channels"),
+        ("FMP4_TRACK_ID", "Track ID"),
+    ];
+    for (key, prompt) in prompts {
+        if already_set.contains(key) {
+            continue;
+        }
+        let default = config.get(key).cloned().unwrap_or_default();
+        let value: String = Input::new().with_prompt(prompt).default(default).interact_text()?;
+        config.insert(key.to_string(), value);
+    }
+    Ok(())
 }
 
 #[derive(Parser, Debug)]
 struct UpdateArgs {
     /// Your Fastly API token
-    #[arg(long)]
-    fastly_token: Option<String>,
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
 
     /// Update only specific keys (comma-separated)
     #[arg(long)]
     keys: Option<String>,
+
+    /// Write every key even if the remote value already matches
+    #[arg(long)]
+    force: bool,
+
+    /// Skip keys whose remote value already matches config.toml, reusing the same
+    /// comparison as `diff` instead of writing and letting the store discard the no-op
+    #[arg(long)]
+    only_drift: bool,
+
+    /// Read and write the KV store over the Fastly REST API instead of the `fastly` CLI,
+    /// for CI containers without the binary
+    #[arg(long)]
+    use_api: bool,
 }
 
 #[derive(Parser, Debug)]
 struct DeployArgs {
     /// Your Fastly API token
-    #[arg(long)]
-    fastly_token: Option<String>,
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
 
     /// Skip building and just deploy
     #[arg(long)]
@@ -64,95 +230,416 @@ struct DeployArgs {
 #[derive(Parser, Debug)]
 struct TailArgs {
     /// Your Fastly API token
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
+
+    /// Only show records at or above this level (debug, info, warn, error)
+    #[arg(long, default_value = "debug")]
+    level: String,
+
+    /// Only show records whose message matches this regex
+    #[arg(long)]
+    grep: Option<String>,
+
+    /// Only show records for this request ID
+    #[arg(long)]
+    request_id: Option<String>,
+
+    /// Print raw JSON records instead of the formatted, leveled output
     #[arg(long)]
-    fastly_token: Option<String>,
+    json: bool,
+}
+
+#[derive(Parser, Debug)]
+struct DevArgs {
+    /// Local address to serve the Compute application on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    addr: String,
+}
+
+#[derive(Parser, Debug)]
+struct StatusArgs {
+    /// Your Fastly API token
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
 }
 
+#[derive(Parser, Debug)]
+struct DiffArgs {
+    /// Your Fastly API token
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
+}
+
+#[derive(Parser, Debug)]
+struct ConfigArgs {
+    /// Your Fastly API token
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
+
+    #[command(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the current value of a single watermarking_config key
+    Get { key: String },
+    /// Set a single watermarking_config key, skipping the write if unchanged
+    Set {
+        key: String,
+        value: String,
+        /// Write even if the remote value already matches
+        #[arg(long)]
+        force: bool,
+    },
+    /// List every known watermarking_config key and its current value
+    List,
+}
+
+/// The only keys `watermarking_config` is allowed to hold.
+const KNOWN_CONFIG_KEYS: &[&str] = &["FMP4_AAC_PROFILE", "FMP4_SAMPLE_RATE", "FMP4_CHANNELS", "FMP4_TRACK_ID"];
+
+fn validate_known_config_key(key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !KNOWN_CONFIG_KEYS.contains(&key) {
+        return Err(format!("Unknown configuration key '{}'; must be one of {:?}", key, KNOWN_CONFIG_KEYS).into());
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct ShellArgs {
+    /// Your Fastly API token, cached for the whole session so it's only entered once
+    #[arg(long, value_parser = parse_secret_string)]
+    fastly_token: Option<SecretString>,
+}
+
+const SHELL_VERBS: &[&str] = &["install", "update", "deploy", "tail", "status", "diff", "get", "set", "help", "exit"];
+const SHELL_HISTORY_FILE: &str = ".stegawave_history";
+
+/// Completes shell verbs, and config keys after `get`/`set`.
+struct ShellHelper;
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = &line[..pos];
+        let word_start = prefix.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word = &prefix[word_start..];
+        let first_word = prefix.split_whitespace().next().unwrap_or("");
+
+        let candidates: Vec<&str> = if word_start == 0 {
+            SHELL_VERBS.to_vec()
+        } else if first_word == "get" || first_word == "set" {
+            KNOWN_CONFIG_KEYS.to_vec()
+        } else {
+            Vec::new()
+        };
+
+        let matches = candidates.into_iter()
+            .filter(|c| c.starts_with(word))
+            .map(|c| Pair { display: c.to_string(), replacement: c.to_string() })
+            .collect();
+        Ok((word_start, matches))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+impl Highlighter for ShellHelper {}
+impl Validator for ShellHelper {}
+impl Helper for ShellHelper {}
+
+fn print_shell_help() {
+    println!("Commands:");
+    println!("  install              run the full install flow");
+    println!("  update               push watermarking_config/secret changes");
+    println!("  deploy               rebuild and redeploy the Compute service");
+    println!("  tail                 stream the service's logs");
+    println!("  status               show the deployed service and store contents");
+    println!("  diff                 compare live store values against config.toml");
+    println!("  get <key>            print a watermarking_config value");
+    println!("  set <key> <value>    write a watermarking_config value");
+    println!("  help                 show this message");
+    println!("  exit                 leave the shell (Ctrl-D also works)");
+}
+
+async fn shell(args: ShellArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", style("StegaWave interactive shell — type 'help' for commands, Ctrl-D to exit.").bold());
+
+    // Cached for the whole session so the operator only authenticates once.
+    let fastly_token = auth::resolve(args.fastly_token, auth::Credential::FastlyToken, profile)?;
+    let fresh_token = || SecretString::new(fastly_token.expose_secret().to_string());
+
+    let rl_config = RustylineConfig::builder()
+        .completion_type(CompletionType::List)
+        .edit_mode(EditMode::Emacs)
+        .build();
+    let mut rl: Editor<ShellHelper, rustyline::history::DefaultHistory> = Editor::with_config(rl_config)?;
+    rl.set_helper(Some(ShellHelper));
+    let _ = rl.load_history(SHELL_HISTORY_FILE);
+
+    loop {
+        match rl.readline("stegawave> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = rl.add_history_entry(line);
+
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let result = match parts.as_slice() {
+                    ["exit"] | ["quit"] => break,
+                    ["help"] => { print_shell_help(); Ok(()) }
+                    ["install"] => install(InstallArgs {
+                        fastly_token: Some(fresh_token()), stegawave_api_key: None, config: None, environment: None, use_api: false,
+                    }, profile).await,
+                    ["update"] => update(UpdateArgs {
+                        fastly_token: Some(fresh_token()), keys: None, force: false, only_drift: false, use_api: false,
+                    }, profile).await,
+                    ["deploy"] => deploy(DeployArgs {
+                        fastly_token: Some(fresh_token()), skip_build: false,
+                    }, profile).await,
+                    ["tail"] => {
+                        let mut printer = rl.create_external_printer()?;
+                        shell_tail(fresh_token(), &mut printer, profile).await
+                    }
+                    ["status"] => status(StatusArgs { fastly_token: Some(fresh_token()) }).await,
+                    ["diff"] => diff_cmd(DiffArgs { fastly_token: Some(fresh_token()) }, profile).await,
+                    ["get", key] => config_cmd(ConfigArgs {
+                        fastly_token: Some(fresh_token()),
+                        action: ConfigAction::Get { key: key.to_string() },
+                    }).await,
+                    ["set", key, value] => config_cmd(ConfigArgs {
+                        fastly_token: Some(fresh_token()),
+                        action: ConfigAction::Set { key: key.to_string(), value: value.to_string(), force: false },
+                    }).await,
+                    _ => {
+                        println!("Unknown command: '{}'. Type 'help' for a list.", line);
+                        Ok(())
+                    }
+                };
+
+                if let Err(e) = result {
+                    println!("{}", style(format!("Error: {}", e)).red());
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("^C");
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("Goodbye!");
+                break;
+            }
+            Err(e) => {
+                println!("{}", style(format!("Readline error: {}", e)).red());
+                break;
+            }
+        }
+    }
+
+    let _ = rl.save_history(SHELL_HISTORY_FILE);
+    Ok(())
+}
+
+
+/// clap `value_parser` that wraps a CLI argument straight into a `SecretString` so it's
+/// zeroized on drop and never appears in a `{:?}` dump of the parsed args.
+fn parse_secret_string(raw: &str) -> Result<SecretString, std::convert::Infallible> {
+    Ok(SecretString::new(raw.to_string()))
+}
+
+fn deserialize_secret_string<'de, D>(deserializer: D) -> Result<SecretString, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let plaintext = String::deserialize(deserializer)?;
+    Ok(SecretString::new(plaintext))
+}
+
+/// Prompts interactively for the Fastly API token when it wasn't passed on the command line.
+fn prompt_fastly_token() -> SecretString {
+    SecretString::new(
+        Password::new()
+            .with_prompt("Enter your Fastly API token")
+            .interact()
+            .unwrap(),
+    )
+}
 
 #[derive(Deserialize, Debug)]
 struct ApiSecret {
-    secret: String,
+    #[serde(deserialize_with = "deserialize_secret_string")]
+    secret: SecretString,
 }
 
-/// Load configuration from CONFIG.txt file
-fn load_config() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let mut config = HashMap::new();
-    
-    // Set default values
-    config.insert("FMP4_AAC_PROFILE".to_string(), "AAC-LC".to_string());
-    config.insert("FMP4_SAMPLE_RATE".to_string(), "44100".to_string());
-    config.insert("FMP4_CHANNELS".to_string(), "2".to_string());
-    config.insert("FMP4_TRACK_ID".to_string(), "1".to_string());
-    
-    // Try to load from CONFIG.txt
-    if let Ok(content) = fs::read_to_string("CONFIG.txt") {
-        for line in content.lines() {
+/// A master secret used only for local Viceroy runs. Never touches the real Secret Store,
+/// so it's fine to keep as a fixed, non-sensitive placeholder.
+const DEV_MASTER_SECRET_HEX: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddee";
+
+const CONFIG_FILE: &str = "config.toml";
+const LEGACY_CONFIG_FILE: &str = "CONFIG.txt";
+const DEFAULT_PROFILE: &str = "default";
+
+/// One named `[profiles.<name>]` block (or `[default]`) in `config.toml`. A named profile
+/// only needs to declare what it overrides — typically `service_id` and maybe a couple of
+/// `FMP4_*` values — since unset fields fall back to whatever `[default]` has.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+struct Profile {
+    service_id: Option<String>,
+    #[serde(rename = "FMP4_AAC_PROFILE", skip_serializing_if = "Option::is_none")]
+    fmp4_aac_profile: Option<String>,
+    #[serde(rename = "FMP4_SAMPLE_RATE", skip_serializing_if = "Option::is_none")]
+    fmp4_sample_rate: Option<String>,
+    #[serde(rename = "FMP4_CHANNELS", skip_serializing_if = "Option::is_none")]
+    fmp4_channels: Option<String>,
+    #[serde(rename = "FMP4_TRACK_ID", skip_serializing_if = "Option::is_none")]
+    fmp4_track_id: Option<String>,
+}
+
+impl Profile {
+    /// Layers `override_profile`'s set fields on top of `self` (the `[default]` profile).
+    fn merged_over(mut self, override_profile: &Profile) -> Profile {
+        macro_rules! take {
+            ($field:ident) => {
+                if override_profile.$field.is_some() {
+                    self.$field = override_profile.$field.clone();
+                }
+            };
+        }
+        take!(service_id);
+        take!(fmp4_aac_profile);
+        take!(fmp4_sample_rate);
+        take!(fmp4_channels);
+        take!(fmp4_track_id);
+        self
+    }
+
+    fn into_config_map(self) -> HashMap<String, String> {
+        let mut config = HashMap::new();
+        config.insert("FMP4_AAC_PROFILE".to_string(), self.fmp4_aac_profile.unwrap_or_else(|| "AAC-LC".to_string()));
+        config.insert("FMP4_SAMPLE_RATE".to_string(), self.fmp4_sample_rate.unwrap_or_else(|| "44100".to_string()));
+        config.insert("FMP4_CHANNELS".to_string(), self.fmp4_channels.unwrap_or_else(|| "2".to_string()));
+        config.insert("FMP4_TRACK_ID".to_string(), self.fmp4_track_id.unwrap_or_else(|| "1".to_string()));
+        if let Some(v) = self.service_id { config.insert("SERVICE_ID".to_string(), v); }
+        config
+    }
+
+    fn from_config_map(config: &HashMap<String, String>) -> Profile {
+        let non_empty = |key: &str| config.get(key).cloned().filter(|v| !v.is_empty());
+        Profile {
+            service_id: non_empty("SERVICE_ID"),
+            fmp4_aac_profile: non_empty("FMP4_AAC_PROFILE"),
+            fmp4_sample_rate: non_empty("FMP4_SAMPLE_RATE"),
+            fmp4_channels: non_empty("FMP4_CHANNELS"),
+            fmp4_track_id: non_empty("FMP4_TRACK_ID"),
+        }
+    }
+}
+
+/// Structured shape of `config.toml`: a `[default]` profile plus any number of named
+/// `[profiles.<name>]` overrides, selected at the CLI with `--profile <name>`.
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct ConfigFile {
+    #[serde(default)]
+    default: Profile,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Reads `config.toml`, migrating a legacy flat `CONFIG.txt` into its `[default]` profile
+/// the first time one is found so existing deployments don't need to do anything by hand.
+fn load_config_file() -> Result<ConfigFile, Box<dyn std::error::Error>> {
+    if let Ok(content) = fs::read_to_string(CONFIG_FILE) {
+        return Ok(toml::from_str(&content)?);
+    }
+
+    if let Ok(legacy) = fs::read_to_string(LEGACY_CONFIG_FILE) {
+        println!("{}", style(format!("Migrating legacy {} into {} as the '{}' profile...", LEGACY_CONFIG_FILE, CONFIG_FILE, DEFAULT_PROFILE)).yellow());
+        let mut flat = HashMap::new();
+        for line in legacy.lines() {
             let line = line.trim();
             if line.starts_with('#') || line.is_empty() {
                 continue;
             }
-            
             if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim().to_string();
                 let value = value.trim().to_string();
                 if !value.is_empty() {
-                    config.insert(key, value);
+                    flat.insert(key.trim().to_string(), value);
                 }
             }
         }
-    }
-    
-    Ok(config)
-}
-
-/// Save configuration to CONFIG.txt file
-fn save_config(config: &HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
-    let content = format!(
-        r#"# StegaWave Configuration
-# This file contains default values for KV store entries.
-# Edit these values as needed for your deployment.
-
-# === Audio Encoding Configuration ===
-# These values control how audio segments are processed for watermarking
-
-# AAC Profile to use for encoding (typically AAC-LC)
-FMP4_AAC_PROFILE={}
-
-# Sample rate in Hz (44100 is standard CD quality)
-FMP4_SAMPLE_RATE={}
+        // Carry any plaintext token/key it held into the keychain rather than dropping it
+        // on the floor — `Profile` no longer has a field to hold them in config.toml.
+        if let Some(token) = flat.get("FASTLY_API_TOKEN").filter(|v| !v.is_empty()) {
+            auth::store(auth::Credential::FastlyToken, DEFAULT_PROFILE, &SecretString::new(token.clone()))?;
+            println!("{}", style("✓ Moved FASTLY_API_TOKEN into the OS keychain").green());
+        }
+        if let Some(key) = flat.get("STEGAWAVE_API_KEY").filter(|v| !v.is_empty()) {
+            auth::store(auth::Credential::StegawaveApiKey, DEFAULT_PROFILE, &SecretString::new(key.clone()))?;
+            println!("{}", style("✓ Moved STEGAWAVE_API_KEY into the OS keychain").green());
+        }
 
-# Number of audio channels (2 for stereo)
-FMP4_CHANNELS={}
+        let config_file = ConfigFile { default: Profile::from_config_map(&flat), profiles: HashMap::new() };
+        save_config_file(&config_file)?;
+        return Ok(config_file);
+    }
 
-# Track ID for the audio track in the FMP4 container
-FMP4_TRACK_ID={}
+    Ok(ConfigFile::default())
+}
 
-# === Service Configuration ===
-# These values are automatically populated during setup but can be updated
+fn save_config_file(config_file: &ConfigFile) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(CONFIG_FILE, toml::to_string_pretty(config_file)?)?;
+    Ok(())
+}
 
-# Your StegaWave API key (will be set during setup)
-STEGAWAVE_API_KEY={}
+/// Loads `profile_name`'s configuration, with `[default]` merged underneath.
+fn load_config(profile_name: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let config_file = load_config_file()?;
+    let profile = match config_file.profiles.get(profile_name) {
+        Some(override_profile) if profile_name != DEFAULT_PROFILE => config_file.default.merged_over(override_profile),
+        _ => config_file.default,
+    };
+    Ok(profile.into_config_map())
+}
 
-# Fastly API token (will be set during setup)
-FASTLY_API_TOKEN={}
+/// Writes `config` into `profile_name`'s block in `config.toml` (`[default]` or a named
+/// `[profiles.<name>]`), leaving every other profile untouched.
+fn save_config(config: &HashMap<String, String>, profile_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config_file = load_config_file()?;
+    let profile = Profile::from_config_map(config);
+    if profile_name == DEFAULT_PROFILE {
+        config_file.default = profile;
+    } else {
+        config_file.profiles.insert(profile_name.to_string(), profile);
+    }
+    save_config_file(&config_file)
+}
 
-# === Advanced Configuration ===
-# These values typically don't need to be changed
+/// Called once `install` has deployed and knows the real `service_id`, so later commands
+/// can resolve it from `config.toml` instead of relying solely on `fastly.toml`.
+fn save_profile_service_id(profile_name: &str, service_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config_file = load_config_file()?;
+    let profile = if profile_name == DEFAULT_PROFILE {
+        &mut config_file.default
+    } else {
+        config_file.profiles.entry(profile_name.to_string()).or_default()
+    };
+    profile.service_id = Some(service_id.to_string());
+    save_config_file(&config_file)
+}
 
-# Watermarking probability (0.01 = 1% chance)
-WATERMARK_PROBABILITY={}
-"#,
-        config.get("FMP4_AAC_PROFILE").unwrap_or(&"AAC-LC".to_string()),
-        config.get("FMP4_SAMPLE_RATE").unwrap_or(&"44100".to_string()),
-        config.get("FMP4_CHANNELS").unwrap_or(&"2".to_string()),
-        config.get("FMP4_TRACK_ID").unwrap_or(&"1".to_string()),
-        config.get("STEGAWAVE_API_KEY").unwrap_or(&"".to_string()),
-        config.get("FASTLY_API_TOKEN").unwrap_or(&"".to_string()),
-        config.get("WATERMARK_PROBABILITY").unwrap_or(&"0.01".to_string()),
-    );
-    
-    fs::write("CONFIG.txt", content)?;
-    Ok(())
+/// Resolves the active profile's `service_id`, falling back to `fastly.toml` for
+/// deployments from before per-profile service binding existed.
+fn resolve_service_id(profile_name: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(service_id) = load_config(profile_name)?.get("SERVICE_ID") {
+        return Ok(service_id.clone());
+    }
+    read_service_id()
 }
 
 /// Prompt user for configuration values
@@ -191,43 +678,534 @@ fn prompt_for_config_values(config: &mut HashMap<String, String>) -> Result<(),
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
+    let profile = cli.profile.as_str();
     match cli.command {
-        Commands::Install(args) => install(args).await?,
-        Commands::Update(args) => update(args).await?,
-        Commands::Deploy(args) => deploy(args).await?,
-        Commands::Tail(args) => tail(args).await?,
+        Commands::Install(args) => install(args, profile).await?,
+        Commands::Update(args) => update(args, profile).await?,
+        Commands::Deploy(args) => deploy(args, profile).await?,
+        Commands::Tail(args) => tail(args, profile).await?,
+        Commands::Dev(args) => dev(args, profile).await?,
+        Commands::Status(args) => status(args).await?,
+        Commands::Config(args) => config_cmd(args).await?,
+        Commands::Diff(args) => diff_cmd(args, profile).await?,
+        Commands::Shell(args) => shell(args, profile).await?,
+        Commands::Login(args) => login(args, profile).await?,
+        Commands::Logout => logout(profile).await?,
     }
 
     Ok(())
 }
 
-async fn tail(args: TailArgs) -> Result<(), Box<dyn std::error::Error>> {
-    println!("{}", style("Tailing logs...").bold());
+/// Stores credentials in the OS keychain for `profile` up front, so later commands never
+/// need to prompt. Separate from `install`, which stores them too, for operators who just
+/// want to rotate a token without re-running the whole install flow.
+async fn login(args: LoginArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let fastly_token = args.fastly_token.unwrap_or_else(prompt_fastly_token);
+    auth::store(auth::Credential::FastlyToken, profile, &fastly_token)?;
+    println!("{}", style(format!("✓ Stored Fastly API token for profile '{}'", profile)).green());
+
+    let stegawave_api_key = match args.stegawave_api_key {
+        Some(key) => Some(key),
+        None if Confirm::new().with_prompt("Also store a StegaWave API key?").default(false).interact()? => Some(SecretString::new(
+            Input::<String>::new().with_prompt("Enter your StegaWave API key").interact_text()?,
+        )),
+        None => None,
+    };
+    if let Some(key) = stegawave_api_key {
+        auth::store(auth::Credential::StegawaveApiKey, profile, &key)?;
+        println!("{}", style(format!("✓ Stored StegaWave API key for profile '{}'", profile)).green());
+    }
+
+    Ok(())
+}
 
+async fn logout(profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    auth::clear(auth::Credential::FastlyToken, profile)?;
+    auth::clear(auth::Credential::StegawaveApiKey, profile)?;
+    println!("{}", style(format!("✓ Cleared stored credentials for profile '{}'", profile)).green());
+    Ok(())
+}
+
+/// Reads `fastly.toml`'s `service_id`, the one piece of state every log-tail invocation needs.
+fn read_service_id() -> Result<String, Box<dyn std::error::Error>> {
     let fastly_toml_str = fs::read_to_string("fastly.toml").map_err(|_| "Failed to read fastly.toml. Have you run `setup install` first?")?;
     let toml_value: Value = toml::from_str(&fastly_toml_str)?;
     let service_id = toml_value["service_id"].as_str().ok_or("service_id not found in fastly.toml. Have you run `setup install` first?")?;
+    Ok(service_id.to_string())
+}
 
-    let fastly_token = args.fastly_token.unwrap_or_else(|| {
-        Password::new()
-            .with_prompt("Enter your Fastly API token")
-            .interact()
-            .unwrap()
-    });
+async fn tail(args: TailArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", style("Tailing logs...").bold());
+
+    let service_id = resolve_service_id(profile)?;
+    let fastly_token = auth::resolve(args.fastly_token, auth::Credential::FastlyToken, profile)?;
+    let filter = log_tail::Filter::new(&args.level, args.grep.as_deref(), args.request_id)?;
+
+    if !args.json {
+        let _ = tracing_subscriber::fmt().with_target(false).without_time().try_init();
+    }
 
     let mut child = Command::new("fastly")
         .arg("log-tail")
         .arg("--service-id")
-        .arg(service_id)
-        .env("FASTLY_API_TOKEN", fastly_token)
+        .arg(&service_id)
+        .arg("--format")
+        .arg("json")
+        .env("FASTLY_API_TOKEN", fastly_token.expose_secret())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().ok_or("failed to capture fastly log-tail stdout")?;
+    let mut buffer = log_tail::PartialLineBuffer::default();
+    for line in std::io::BufReader::new(stdout).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let Some((record, raw)) = buffer.push(&line) else { continue };
+        if !filter.matches(&record, &raw) {
+            continue;
+        }
+        if args.json {
+            println!("{}", raw);
+        } else {
+            log_tail::emit(&record, &raw);
+        }
+    }
+
+    child.wait()?;
+
+    Ok(())
+}
+
+/// Same log stream as `tail`, but routed through an `ExternalPrinter` so lines arriving
+/// between keystrokes get written above the prompt and the prompt is redrawn afterward,
+/// instead of interleaving with whatever the operator is currently typing.
+async fn shell_tail(
+    fastly_token: SecretString,
+    printer: &mut impl ExternalPrinter,
+    profile: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service_id = resolve_service_id(profile)?;
+
+    let mut child = Command::new("fastly")
+        .arg("log-tail")
+        .arg("--service-id")
+        .arg(&service_id)
+        .env("FASTLY_API_TOKEN", fastly_token.expose_secret())
+        .stdout(Stdio::piped())
         .spawn()?;
 
+    let stdout = child.stdout.take().ok_or("failed to capture fastly log-tail stdout")?;
+    for line in std::io::BufReader::new(stdout).lines() {
+        match line {
+            Ok(line) => printer.print(line)?,
+            Err(_) => break,
+        }
+    }
+
     child.wait()?;
 
     Ok(())
 }
 
-async fn install(args: InstallArgs) -> Result<(), Box<dyn std::error::Error>> {
+/// Renders `rows` under `headers` as a column-aligned table, padding each column to the
+/// width of its longest cell instead of relying on fixed-width guesses.
+fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(console::measure_text_width(cell));
+        }
+    }
+
+    // Cells may carry ANSI color codes (e.g. a styled drift indicator), so pad by
+    // displayed width via `console::pad_str` rather than `{:<width$}`, which would
+    // count escape bytes as visible characters and throw off alignment.
+    let pad_row = |cells: &[String], widths: &[usize]| -> String {
+        cells.iter().zip(widths)
+            .map(|(cell, width)| console::pad_str(cell, *width, console::Alignment::Left, None).to_string())
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    let header_cells: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    let separator: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+
+    let mut out = String::new();
+    out.push_str(pad_row(&header_cells, &widths).trim_end());
+    out.push('\n');
+    out.push_str(pad_row(&separator, &widths).trim_end());
+    out.push('\n');
+    for row in rows {
+        out.push_str(pad_row(row, &widths).trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+async fn list_kv_store_keys(store_id: &str, token: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = Command::new("fastly")
+        .arg("kv-store-entry")
+        .arg("list")
+        .arg("--store-id")
+        .arg(store_id)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!("Failed to list KV store entries: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(json["Data"].as_array()
+        .map(|entries| entries.iter().filter_map(|e| e["Key"].as_str().map(String::from)).collect())
+        .unwrap_or_default())
+}
+
+async fn get_kv_store_entry_value(store_id: &str, key: &str, token: &str, use_api: bool) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if use_api {
+        return http::get_kv_store_entry_value(&Client::new(), store_id, key, token).await;
+    }
+
+    let output = Command::new("fastly")
+        .arg("kv-store-entry")
+        .arg("describe")
+        .arg("--store-id")
+        .arg(store_id)
+        .arg("--key")
+        .arg(key)
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+async fn list_secret_store_keys(store_id: &str, token: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = Command::new("fastly")
+        .arg("secret-store-entry")
+        .arg("list")
+        .arg("--store-id")
+        .arg(store_id)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!("Failed to list secret store entries: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(json["Data"].as_array()
+        .map(|entries| entries.iter().filter_map(|e| e["Name"].as_str().map(String::from)).collect())
+        .unwrap_or_default())
+}
+
+/// Secret store entries are write-only — the API never echoes plaintext back — but
+/// `describe` does return a SHA-256 `digest` of the stored value, which is enough to
+/// tell whether it matches a local value without ever reading either one aloud.
+async fn get_secret_store_entry_digest(store_id: &str, key: &str, token: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let output = Command::new("fastly")
+        .arg("secret-store-entry")
+        .arg("describe")
+        .arg("--store-id")
+        .arg(store_id)
+        .arg("--name")
+        .arg(key)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(json["digest"].as_str().map(String::from))
+}
+
+fn sha256_hex(value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(value.as_bytes());
+    hex_encode(&hasher.finalize())
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn status(args: StatusArgs) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", style("Service Status").bold());
+
+    let fastly_toml_str = fs::read_to_string("fastly.toml").map_err(|_| "Failed to read fastly.toml. Have you run `setup install` first?")?;
+    let toml_value: Value = toml::from_str(&fastly_toml_str)?;
+    let service_id = toml_value["service_id"].as_str().ok_or("service_id not found in fastly.toml. Have you run `setup install` first?")?;
+
+    let fastly_token = args.fastly_token.unwrap_or_else(prompt_fastly_token);
+
+    let describe_output = Command::new("fastly")
+        .arg("service")
+        .arg("describe")
+        .arg("--service-id")
+        .arg(service_id)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", fastly_token.expose_secret())
+        .output()?;
+    if !describe_output.status.success() {
+        return Err(format!("Failed to describe service: {}", String::from_utf8_lossy(&describe_output.stderr)).into());
+    }
+    let service_json: Value = serde_json::from_slice(&describe_output.stdout)?;
+    let active_version = service_json.get("ActiveVersion")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "none".to_string());
+
+    let domain_output = Command::new("fastly")
+        .arg("domain")
+        .arg("list")
+        .arg("--service-id")
+        .arg(service_id)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", fastly_token.expose_secret())
+        .output()?;
+    let domain = if domain_output.status.success() {
+        serde_json::from_slice::<Vec<Value>>(&domain_output.stdout)
+            .ok()
+            .and_then(|domains| domains.first().and_then(|d| d["Name"].as_str().map(String::from)))
+            .unwrap_or_else(|| "N/A".to_string())
+    } else {
+        "N/A".to_string()
+    };
+
+    println!("\n{}", style("=== Service ===").bold());
+    print!("{}", render_table(&["Field", "Value"], &[
+        vec!["Domain".to_string(), domain],
+        vec!["Active Version".to_string(), active_version],
+    ]));
+
+    let watermarking_config_id = get_kv_store_id("watermarking_config", fastly_token.expose_secret(), false).await?;
+    let watermarking_keys = list_kv_store_keys(&watermarking_config_id, fastly_token.expose_secret()).await?;
+    let secrets_id = get_secret_store_id("secrets", fastly_token.expose_secret()).await?;
+    let secrets_keys = list_secret_store_keys(&secrets_id, fastly_token.expose_secret()).await?;
+    let api_keys_id = get_secret_store_id("api_keys", fastly_token.expose_secret()).await?;
+    let api_keys_keys = list_secret_store_keys(&api_keys_id, fastly_token.expose_secret()).await?;
+
+    println!("\n{}", style("=== Stores ===").bold());
+    print!("{}", render_table(&["Store", "Type", "Keys"], &[
+        vec!["watermarking_config".to_string(), "kv".to_string(), watermarking_keys.len().to_string()],
+        vec!["secrets".to_string(), "secret".to_string(), secrets_keys.len().to_string()],
+        vec!["api_keys".to_string(), "secret".to_string(), api_keys_keys.len().to_string()],
+    ]));
+
+    let mut config_rows = Vec::new();
+    for key in &watermarking_keys {
+        let value = get_kv_store_entry_value(&watermarking_config_id, key, fastly_token.expose_secret(), false).await?
+            .unwrap_or_else(|| "?".to_string());
+        let display = if key.contains("SECRET") { "[REDACTED]".to_string() } else { value };
+        config_rows.push(vec![key.clone(), display]);
+    }
+    println!("\n{}", style("=== Watermarking Config ===").bold());
+    print!("{}", render_table(&["Key", "Value"], &config_rows));
+
+    Ok(())
+}
+
+async fn config_cmd(args: ConfigArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let fastly_token = args.fastly_token.unwrap_or_else(prompt_fastly_token);
+    let watermarking_config_id = get_kv_store_id("watermarking_config", fastly_token.expose_secret(), false).await?;
+
+    match args.action {
+        ConfigAction::Get { key } => {
+            validate_known_config_key(&key)?;
+            match get_kv_store_entry_value(&watermarking_config_id, &key, fastly_token.expose_secret(), false).await? {
+                Some(value) => println!("{} = {}", key, value),
+                None => println!("{} is not set", key),
+            }
+        }
+        ConfigAction::Set { key, value, force } => {
+            validate_known_config_key(&key)?;
+            populate_kv_store_entry(&watermarking_config_id, &key, &value, fastly_token.expose_secret(), force, false).await?;
+        }
+        ConfigAction::List => {
+            let mut rows = Vec::new();
+            for key in KNOWN_CONFIG_KEYS {
+                let value = get_kv_store_entry_value(&watermarking_config_id, key, fastly_token.expose_secret(), false).await?
+                    .unwrap_or_else(|| "(unset)".to_string());
+                rows.push(vec![key.to_string(), value]);
+            }
+            print!("{}", render_table(&["Key", "Value"], &rows));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a config key's live store value matches, differs from, or is missing
+/// compared to the local config.toml value.
+enum DriftStatus {
+    Matched,
+    Changed,
+    Missing,
+}
+
+impl DriftStatus {
+    /// Colored indicator word for the `diff` table's Status column.
+    fn label(&self) -> String {
+        match self {
+            DriftStatus::Matched => style("matched").green().to_string(),
+            DriftStatus::Changed => style("changed").yellow().to_string(),
+            DriftStatus::Missing => style("missing").red().to_string(),
+        }
+    }
+}
+
+/// Compares `key`'s live value against `local_value`, returning the drift status plus
+/// the remote/local cells to display. `STEGAWAVE_API_KEY` lives in the `api_keys` secret
+/// store and, like any key containing `SECRET`, is compared by SHA-256 digest and shown
+/// as `[REDACTED]` rather than echoed — consistent with `populate_kv_store_entry`.
+async fn diff_entry(
+    watermarking_config_id: &str,
+    api_keys_id: &str,
+    key: &str,
+    local_value: Option<&str>,
+    token: &str,
+) -> Result<(DriftStatus, String, String), Box<dyn std::error::Error>> {
+    let is_secret = key.contains("SECRET") || key == "STEGAWAVE_API_KEY";
+
+    let (remote_value, remote_matches_local) = if key == "STEGAWAVE_API_KEY" {
+        let digest = get_secret_store_entry_digest(api_keys_id, "service_api_key", token).await?;
+        let matches = match (&digest, local_value) {
+            (Some(d), Some(local)) => *d == sha256_hex(local),
+            _ => false,
+        };
+        (digest, matches)
+    } else {
+        let value = get_kv_store_entry_value(watermarking_config_id, key, token, false).await?;
+        let matches = matches!((&value, local_value), (Some(v), Some(local)) if v == local);
+        (value, matches)
+    };
+
+    let status = match (&remote_value, local_value) {
+        (None, _) => DriftStatus::Missing,
+        (Some(_), _) if remote_matches_local => DriftStatus::Matched,
+        (Some(_), _) => DriftStatus::Changed,
+    };
+
+    let redact = |v: Option<String>| if is_secret {
+        v.map(|_| "[REDACTED]".to_string()).unwrap_or_else(|| "(unset)".to_string())
+    } else {
+        v.unwrap_or_else(|| "(unset)".to_string())
+    };
+
+    Ok((status, redact(remote_value), redact(local_value.map(String::from))))
+}
+
+async fn diff_cmd(args: DiffArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", style("Configuration Drift").bold());
+
+    let fastly_token = args.fastly_token.unwrap_or_else(prompt_fastly_token);
+    let mut config = load_config(profile)?;
+
+    // STEGAWAVE_API_KEY now lives in the keychain rather than config.toml — look it up there
+    // so the drift comparison below has a local value to compare against instead of always
+    // treating it as Missing.
+    if let Some(key) = auth::peek(auth::Credential::StegawaveApiKey, profile) {
+        config.insert("STEGAWAVE_API_KEY".to_string(), key.expose_secret().to_string());
+    }
+
+    let watermarking_config_id = get_kv_store_id("watermarking_config", fastly_token.expose_secret(), false).await?;
+    let api_keys_id = get_secret_store_id("api_keys", fastly_token.expose_secret()).await?;
+
+    let mut rows = Vec::new();
+    for key in KNOWN_CONFIG_KEYS.iter().map(|k| k.to_string()).chain(std::iter::once("STEGAWAVE_API_KEY".to_string())) {
+        let (status, remote, local) = diff_entry(
+            &watermarking_config_id, &api_keys_id, &key, config.get(&key).map(String::as_str), fastly_token.expose_secret(),
+        ).await?;
+        rows.push(vec![key, remote, local, status.label()]);
+    }
+
+    print!("{}", render_table(&["Key", "Remote", "Local", "Status"], &rows));
+    Ok(())
+}
+
+async fn dev(args: DevArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", style("Starting local Compute development server (Viceroy)...").bold());
+
+    if Command::new("fastly").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_err() {
+        println!("{}", style("Fastly CLI not found. Please install it first:").red());
+        println!("https://developer.fastly.com/learning/tools/cli/#installation");
+        return Ok(());
+    }
+
+    let config = load_config(profile)?;
+    // The keychain holds the real key; fall back to an obvious dummy so `dev` still works
+    // offline for an operator who hasn't run `login`/`install` yet.
+    let stegawave_api_key = auth::peek(auth::Credential::StegawaveApiKey, profile)
+        .map(|key| key.expose_secret().to_string())
+        .unwrap_or_else(|| "dev-api-key".to_string());
+    fs::write("viceroy.toml", build_local_server_config(&config, &stegawave_api_key))?;
+    println!("{}", style("✓ Wrote emulated store layout to viceroy.toml").green());
+
+    println!("Launching emulator on {}...", args.addr);
+    let status = Command::new("fastly")
+        .arg("compute")
+        .arg("serve")
+        .arg("--config")
+        .arg("viceroy.toml")
+        .arg("--addr")
+        .arg(&args.addr)
+        .status()?;
+
+    if !status.success() {
+        println!("{}", style("Local development server exited with an error.").red());
+    }
+
+    Ok(())
+}
+
+/// Builds a `[local_server]` TOML fragment so Viceroy sees the same KV/Secret store keys
+/// that `install()` would provision in production, without needing a Fastly token.
+fn build_local_server_config(config: &HashMap<String, String>, stegawave_api_key: &str) -> String {
+    format!(
+        r#"# Generated by `setup-tool dev` — emulated store layout for local Viceroy runs.
+# Mirrors the stores provisioned by `setup-tool install`; edit config.toml and rerun
+# `setup-tool dev` to pick up new FMP4_* values.
+
+[local_server.kv_stores.watermarking_config]
+format = "inline-toml"
+
+[local_server.kv_stores.watermarking_config.contents]
+FMP4_AAC_PROFILE = "{}"
+FMP4_SAMPLE_RATE = "{}"
+FMP4_CHANNELS = "{}"
+FMP4_TRACK_ID = "{}"
+
+[local_server.kv_stores.watermarking_circuit_breaker]
+format = "inline-toml"
+
+[local_server.secret_stores.secrets]
+[[local_server.secret_stores.secrets.entries]]
+key = "SECRET_KEY_HEX"
+data = "{}"
+
+[local_server.secret_stores.api_keys]
+[[local_server.secret_stores.api_keys.entries]]
+key = "service_api_key"
+data = "{}"
+"#,
+        config.get("FMP4_AAC_PROFILE").unwrap_or(&"AAC-LC".to_string()),
+        config.get("FMP4_SAMPLE_RATE").unwrap_or(&"44100".to_string()),
+        config.get("FMP4_CHANNELS").unwrap_or(&"2".to_string()),
+        config.get("FMP4_TRACK_ID").unwrap_or(&"1".to_string()),
+        DEV_MASTER_SECRET_HEX,
+        stegawave_api_key,
+    )
+}
+
+async fn install(args: InstallArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", style("Welcome to the StegaWave Fastly Compute@Edge Setup").bold());
 
     // Check for Fastly CLI
@@ -239,47 +1217,70 @@ async fn install(args: InstallArgs) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", style("✓ Fastly CLI is installed.").green());
 
     // Load existing configuration
-    let mut config = load_config()?;
-    
-    // Get API credentials
-    let fastly_token = args.fastly_token.unwrap_or_else(|| {
-        Password::new()
-            .with_prompt("Enter your Fastly API token")
-            .interact()
-            .unwrap()
-    });
+    let mut config = load_config(profile)?;
 
-    let stegawave_api_key = args.stegawave_api_key.unwrap_or_else(|| {
-        Input::new()
-            .with_prompt("Enter your StegaWave API key")
-            .interact_text()
-            .unwrap()
-    });
+    // Load and validate the declarative manifest, if one was given, before touching any
+    // FMP4_* values or kicking off a build/deploy.
+    let manifest = match &args.config {
+        Some(path) => Some(load_manifest(path)?),
+        None => None,
+    };
+    if let Some(manifest) = &manifest {
+        if let Some(watermarking) = &manifest.watermarking {
+            validate_watermarking(watermarking)?;
+        }
+        if let Some(environments) = &manifest.environments {
+            for env in environments {
+                validate_watermarking(&env.watermarking)?;
+            }
+        }
+    }
+
+    // Get API credentials: explicit flag, then the OS keychain, then FASTLY_API_TOKEN/
+    // STEGAWAVE_API_KEY in the environment, and only then an interactive prompt.
+    let fastly_token = auth::resolve(args.fastly_token, auth::Credential::FastlyToken, profile)?;
+    let stegawave_api_key = auth::resolve(args.stegawave_api_key, auth::Credential::StegawaveApiKey, profile)?;
 
-    // Store credentials in config
-    config.insert("FASTLY_API_TOKEN".to_string(), fastly_token.clone());
-    config.insert("STEGAWAVE_API_KEY".to_string(), stegawave_api_key.clone());
+    // Persist both in the keychain so the next `install`/`update`/`deploy`/`tail` for this
+    // profile doesn't need to ask again — neither ever touches config.toml in plaintext.
+    auth::store(auth::Credential::FastlyToken, profile, &fastly_token)?;
+    auth::store(auth::Credential::StegawaveApiKey, profile, &stegawave_api_key)?;
 
-    // Prompt for audio encoding configuration
+    // Configure audio encoding: apply the manifest non-interactively when one was given,
+    // falling back to prompts only for keys it didn't set; otherwise prompt as before.
     println!("\n{}", style("=== Configuration ===").bold());
-    if Confirm::new()
+    if let Some(manifest) = &manifest {
+        let mut already_set = HashSet::new();
+        if let Some(watermarking) = &manifest.watermarking {
+            already_set.extend(apply_manifest_watermarking(watermarking, &mut config));
+        }
+        if let Some(env_name) = &args.environment {
+            let env = manifest.environments.as_ref()
+                .and_then(|envs| envs.iter().find(|e| &e.name == env_name))
+                .ok_or_else(|| format!("Environment '{}' not found in manifest", env_name))?;
+            already_set.extend(apply_manifest_watermarking(&env.watermarking, &mut config));
+            println!("✓ Applied '{}' environment overrides", env_name);
+        }
+        println!("{}", style("✓ Applied watermarking config from manifest").green());
+        prompt_for_missing_config_values(&mut config, &already_set)?;
+    } else if Confirm::new()
         .with_prompt("Do you want to configure audio encoding parameters?")
         .default(true)
-        .interact()? 
+        .interact()?
     {
         prompt_for_config_values(&mut config)?;
     }
 
     // Save configuration
-    save_config(&config)?;
-    println!("{}", style("✓ Configuration saved to CONFIG.txt").green());
+    save_config(&config, profile)?;
+    println!("{}", style("✓ Configuration saved to config.toml").green());
 
     // Fetch Master Secret
     println!("Fetching master secret from StegaWave API...");
     let client = Client::new();
     let res = client
         .get("https://api.stegawave.com/getsecret")
-        .header("X-API-Key", &stegawave_api_key)
+        .header("X-API-Key", stegawave_api_key.expose_secret())
         .send()
         .await?;
 
@@ -328,43 +1329,67 @@ async fn install(args: InstallArgs) -> Result<(), Box<dyn std::error::Error>> {
         let toml_value: Value = toml::from_str(&fastly_toml_str)?;
         let service_id = toml_value["service_id"].as_str().unwrap();
 
-        // Create KV Stores
+        // Record the deployed service against this profile so later commands (tail, diff,
+        // update --only-drift) can resolve it without re-reading fastly.toml.
+        save_profile_service_id(profile, service_id)?;
+
+        // Create stores: cryptographic material goes in the Secret Store (encrypted at
+        // rest, never echoed back), non-sensitive watermarking config stays in KV.
+        println!("Creating secret stores...");
+        create_secret_store("secrets", fastly_token.expose_secret()).await?;
+        create_secret_store("api_keys", fastly_token.expose_secret()).await?;
+        println!("{}", style("✓ Secret stores created.").green());
+
         println!("Creating KV stores...");
-        create_kv_store("secrets", &fastly_token).await?;
-        create_kv_store("api_keys", &fastly_token).await?;
-        create_kv_store("watermarking_config", &fastly_token).await?;
+        create_kv_store("watermarking_config", fastly_token.expose_secret(), args.use_api).await?;
+        // Written at runtime by the edge service's circuit breaker — created empty here so
+        // CircuitBreaker::open() finds a store to write to instead of silently no-op'ing.
+        create_kv_store("watermarking_circuit_breaker", fastly_token.expose_secret(), args.use_api).await?;
         println!("{}", style("✓ KV stores created.").green());
 
-        // Populate KV Stores
-        println!("Populating KV stores...");
-        
-        println!("Getting secrets KV store ID...");
-        let secrets_id = get_kv_store_id("secrets", &fastly_token).await?;
-        println!("✓ Got secrets KV store ID: {}", secrets_id);
-        
-        println!("Getting api_keys KV store ID...");
-        let api_keys_id = get_kv_store_id("api_keys", &fastly_token).await?;
-        println!("✓ Got api_keys KV store ID: {}", api_keys_id);
-        
+        // Populate stores
+        println!("Populating stores...");
+
+        println!("Getting secrets secret store ID...");
+        let secrets_id = get_secret_store_id("secrets", fastly_token.expose_secret()).await?;
+        println!("✓ Got secrets secret store ID: {}", secrets_id);
+
+        println!("Getting api_keys secret store ID...");
+        let api_keys_id = get_secret_store_id("api_keys", fastly_token.expose_secret()).await?;
+        println!("✓ Got api_keys secret store ID: {}", api_keys_id);
+
         println!("Getting watermarking_config KV store ID...");
-        let watermarking_config_id = get_kv_store_id("watermarking_config", &fastly_token).await?;
+        let watermarking_config_id = get_kv_store_id("watermarking_config", fastly_token.expose_secret(), args.use_api).await?;
         println!("✓ Got watermarking_config KV store ID: {}", watermarking_config_id);
 
+        println!("Getting watermarking_circuit_breaker KV store ID...");
+        let circuit_breaker_id = get_kv_store_id("watermarking_circuit_breaker", fastly_token.expose_secret(), args.use_api).await?;
+        println!("✓ Got watermarking_circuit_breaker KV store ID: {}", circuit_breaker_id);
+
         // Populate with secrets and API keys
-        populate_kv_store_entry(&secrets_id, "SECRET_KEY_HEX", &api_secret.secret, &fastly_token).await?;
-        populate_kv_store_entry(&api_keys_id, "service_api_key", &stegawave_api_key, &fastly_token).await?;
-        
-        // Populate with configuration values
-        populate_kv_store_entry(&watermarking_config_id, "FMP4_AAC_PROFILE", 
-            config.get("FMP4_AAC_PROFILE").unwrap_or(&"AAC-LC".to_string()), &fastly_token).await?;
-        populate_kv_store_entry(&watermarking_config_id, "FMP4_SAMPLE_RATE", 
-            config.get("FMP4_SAMPLE_RATE").unwrap_or(&"44100".to_string()), &fastly_token).await?;
-        populate_kv_store_entry(&watermarking_config_id, "FMP4_CHANNELS", 
-            config.get("FMP4_CHANNELS").unwrap_or(&"2".to_string()), &fastly_token).await?;
-        populate_kv_store_entry(&watermarking_config_id, "FMP4_TRACK_ID", 
-            config.get("FMP4_TRACK_ID").unwrap_or(&"1".to_string()), &fastly_token).await?;
-        
-        println!("{}", style("✓ KV stores populated.").green());
+        populate_secret_store_entry(&secrets_id, "SECRET_KEY_HEX", api_secret.secret.expose_secret(), fastly_token.expose_secret()).await?;
+        populate_secret_store_entry(&api_keys_id, "service_api_key", stegawave_api_key.expose_secret(), fastly_token.expose_secret()).await?;
+
+        // Populate with configuration values. install() may be re-run against an existing
+        // service, so entries are only written when their value actually changed.
+        populate_kv_store_entry(&watermarking_config_id, "FMP4_AAC_PROFILE",
+            config.get("FMP4_AAC_PROFILE").unwrap_or(&"AAC-LC".to_string()), fastly_token.expose_secret(), false, args.use_api).await?;
+        populate_kv_store_entry(&watermarking_config_id, "FMP4_SAMPLE_RATE",
+            config.get("FMP4_SAMPLE_RATE").unwrap_or(&"44100".to_string()), fastly_token.expose_secret(), false, args.use_api).await?;
+        populate_kv_store_entry(&watermarking_config_id, "FMP4_CHANNELS",
+            config.get("FMP4_CHANNELS").unwrap_or(&"2".to_string()), fastly_token.expose_secret(), false, args.use_api).await?;
+        populate_kv_store_entry(&watermarking_config_id, "FMP4_TRACK_ID",
+            config.get("FMP4_TRACK_ID").unwrap_or(&"1".to_string()), fastly_token.expose_secret(), false, args.use_api).await?;
+
+        println!("{}", style("✓ Stores populated.").green());
+
+        // Bind each store into fastly.toml's resource links so the Compute service can see it.
+        println!("Linking resources into fastly.toml...");
+        link_resource(&secrets_id, "secrets", fastly_token.expose_secret()).await?;
+        link_resource(&api_keys_id, "api_keys", fastly_token.expose_secret()).await?;
+        link_resource(&watermarking_config_id, "watermarking_config", fastly_token.expose_secret()).await?;
+        link_resource(&circuit_breaker_id, "watermarking_circuit_breaker", fastly_token.expose_secret()).await?;
+        println!("{}", style("✓ Resources linked.").green());
 
         println!("\n{}", style("Setup Complete!").bold().green());
         let service_domain_output = Command::new("fastly").arg("service").arg("describe").arg("--service-id").arg(service_id).output()?;
@@ -373,7 +1398,7 @@ async fn install(args: InstallArgs) -> Result<(), Box<dyn std::error::Error>> {
         let domain = domain_line.split_whitespace().last().unwrap_or("N/A");
         println!("Service Domain: {}", style(domain).cyan());
         println!("\n{}", style("Next Steps:").bold());
-        println!("• Edit CONFIG.txt to modify configuration values");
+        println!("• Edit config.toml to modify configuration values");
         println!("• Run 'setup-tool update' to update KV stores");
         println!("• Run 'setup-tool deploy' to redeploy after code changes");
         println!("• Run 'setup-tool tail' to view logs");
@@ -388,9 +1413,121 @@ async fn install(args: InstallArgs) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn create_kv_store(name: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn create_secret_store(name: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Creating secret store: {}", name);
+
+    let output = Command::new("fastly")
+        .arg("secret-store")
+        .arg("create")
+        .arg("--name")
+        .arg(name)
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        if stderr.contains("already exists") || stdout.contains("already exists") {
+            println!("✓ Secret store '{}' already exists", name);
+        } else {
+            println!("Error creating secret store '{}': {}", name, stderr);
+            println!("Stdout: {}", stdout);
+            return Err(format!("Failed to create secret store '{}': {}", name, stderr).into());
+        }
+    } else {
+        println!("✓ Created secret store: {}", name);
+    }
+    Ok(())
+}
+
+async fn get_secret_store_id(name: &str, token: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("fastly")
+        .arg("secret-store")
+        .arg("describe")
+        .arg("--name")
+        .arg(name)
+        .arg("--json")
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(format!("Failed to describe secret store '{}': {}\nStdout: {}", name, stderr, stdout).into());
+    }
+
+    let stdout_str = String::from_utf8_lossy(&output.stdout);
+    if stdout_str.trim().is_empty() {
+        return Err(format!("Empty response when describing secret store '{}'", name).into());
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let id = json["id"].as_str()
+        .ok_or_else(|| format!("Secret store '{}' does not have an 'id' field in response", name))?;
+    Ok(id.to_string())
+}
+
+async fn populate_secret_store_entry(store_id: &str, key: &str, value: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Setting secret store entry: {} = [REDACTED]", key);
+
+    let output = Command::new("fastly")
+        .arg("secret-store-entry")
+        .arg("create")
+        .arg("--store-id")
+        .arg(store_id)
+        .arg("--name")
+        .arg(key)
+        .arg("--value")
+        .arg(value)
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(format!("Failed to create secret store entry '{}': {}\nStdout: {}", key, stderr, stdout).into());
+    }
+
+    println!("✓ Successfully set secret store entry: {}", key);
+    Ok(())
+}
+
+/// Binds a provisioned store to the deployed service so it's reachable under `link_name`
+/// from the Compute application (equivalent to `fastly resource-link create`).
+async fn link_resource(resource_id: &str, link_name: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("fastly")
+        .arg("resource-link")
+        .arg("create")
+        .arg("--version")
+        .arg("latest")
+        .arg("--resource-id")
+        .arg(resource_id)
+        .arg("--name")
+        .arg(link_name)
+        .env("FASTLY_API_TOKEN", token)
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("already exists") {
+            println!("✓ Resource link '{}' already exists", link_name);
+            return Ok(());
+        }
+        return Err(format!("Failed to link resource '{}': {}", link_name, stderr).into());
+    }
+
+    println!("✓ Linked resource '{}' into fastly.toml", link_name);
+    Ok(())
+}
+
+async fn create_kv_store(name: &str, token: &str, use_api: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if use_api {
+        return http::create_kv_store(&Client::new(), name, token).await;
+    }
+
     println!("Creating KV store: {}", name);
-    
+
     let output = Command::new("fastly")
         .arg("kv-store")
         .arg("create")
@@ -416,7 +1553,11 @@ async fn create_kv_store(name: &str, token: &str) -> Result<(), Box<dyn std::err
     Ok(())
 }
 
-async fn get_kv_store_id(name: &str, token: &str) -> Result<String, Box<dyn std::error::Error>> {
+async fn get_kv_store_id(name: &str, token: &str, use_api: bool) -> Result<String, Box<dyn std::error::Error>> {
+    if use_api {
+        return http::get_kv_store_id(&Client::new(), name, token).await;
+    }
+
     let output = Command::new("fastly")
         .arg("kv-store")
         .arg("describe")
@@ -443,9 +1584,24 @@ async fn get_kv_store_id(name: &str, token: &str) -> Result<String, Box<dyn std:
 }
 
 
-async fn populate_kv_store_entry(store_id: &str, key: &str, value: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Writes a KV store entry, but skips the round-trip when the remote value already matches
+/// (unless `force` is set), so operators can tweak one key without clobbering the rest.
+async fn populate_kv_store_entry(store_id: &str, key: &str, value: &str, token: &str, force: bool, use_api: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if !force {
+        if let Some(existing) = get_kv_store_entry_value(store_id, key, token, use_api).await? {
+            if existing == value {
+                println!("✓ KV store entry '{}' already up to date, skipping", key);
+                return Ok(());
+            }
+        }
+    }
+
+    if use_api {
+        return http::populate_kv_store_entry(&Client::new(), store_id, key, value, token).await;
+    }
+
     println!("Setting KV store entry: {} = {}", key, if key.contains("SECRET") { "[REDACTED]" } else { value });
-    
+
     let output = Command::new("fastly")
         .arg("kv-store-entry")
         .arg("create")
@@ -468,19 +1624,14 @@ async fn populate_kv_store_entry(store_id: &str, key: &str, value: &str, token:
     Ok(())
 }
 
-async fn update(args: UpdateArgs) -> Result<(), Box<dyn std::error::Error>> {
+async fn update(args: UpdateArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", style("Updating KV store values...").bold());
 
-    let fastly_token = args.fastly_token.unwrap_or_else(|| {
-        Password::new()
-            .with_prompt("Enter your Fastly API token")
-            .interact()
-            .unwrap()
-    });
+    let fastly_token = auth::resolve(args.fastly_token, auth::Credential::FastlyToken, profile)?;
 
     // Load configuration
-    let mut config = load_config()?;
-    
+    let mut config = load_config(profile)?;
+
     // Check if we should update specific keys only
     let keys_to_update: Vec<String> = if let Some(keys_str) = args.keys {
         keys_str.split(',').map(|s| s.trim().to_string()).collect()
@@ -494,19 +1645,35 @@ async fn update(args: UpdateArgs) -> Result<(), Box<dyn std::error::Error>> {
         ]
     };
 
-    // Get KV store IDs
-    let watermarking_config_id = get_kv_store_id("watermarking_config", &fastly_token).await?;
-    let api_keys_id = get_kv_store_id("api_keys", &fastly_token).await?;
+    // Get store IDs
+    let watermarking_config_id = get_kv_store_id("watermarking_config", fastly_token.expose_secret(), args.use_api).await?;
+    let api_keys_id = get_secret_store_id("api_keys", fastly_token.expose_secret()).await?;
+
+    // STEGAWAVE_API_KEY now lives in the keychain rather than config.toml, so look it up
+    // there when an explicit `--keys STEGAWAVE_API_KEY` asks for it.
+    if keys_to_update.iter().any(|k| k == "STEGAWAVE_API_KEY") {
+        if let Some(key) = auth::peek(auth::Credential::StegawaveApiKey, profile) {
+            config.insert("STEGAWAVE_API_KEY".to_string(), key.expose_secret().to_string());
+        }
+    }
 
     // Update specified keys
     for key in &keys_to_update {
         if let Some(value) = config.get(key) {
+            if args.only_drift {
+                let (status, ..) = diff_entry(&watermarking_config_id, &api_keys_id, key, Some(value.as_str()), fastly_token.expose_secret()).await?;
+                if matches!(status, DriftStatus::Matched) {
+                    println!("✓ '{}' already up to date, skipping", key);
+                    continue;
+                }
+            }
+
             match key.as_str() {
                 "FMP4_AAC_PROFILE" | "FMP4_SAMPLE_RATE" | "FMP4_CHANNELS" | "FMP4_TRACK_ID" => {
-                    populate_kv_store_entry(&watermarking_config_id, key, value, &fastly_token).await?;
+                    populate_kv_store_entry(&watermarking_config_id, key, value, fastly_token.expose_secret(), args.force, args.use_api).await?;
                 }
                 "STEGAWAVE_API_KEY" => {
-                    populate_kv_store_entry(&api_keys_id, "service_api_key", value, &fastly_token).await?;
+                    populate_secret_store_entry(&api_keys_id, "service_api_key", value, fastly_token.expose_secret()).await?;
                 }
                 _ => {
                     println!("Unknown configuration key: {}", key);
@@ -515,19 +1682,18 @@ async fn update(args: UpdateArgs) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    println!("{}", style("✓ KV store values updated successfully.").green());
+    println!("{}", style("✓ Store values updated successfully.").green());
     Ok(())
 }
 
-async fn deploy(args: DeployArgs) -> Result<(), Box<dyn std::error::Error>> {
+async fn deploy(args: DeployArgs, profile: &str) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", style("Deploying Fastly service...").bold());
 
-    let fastly_token = args.fastly_token.unwrap_or_else(|| {
-        Password::new()
-            .with_prompt("Enter your Fastly API token")
-            .interact()
-            .unwrap()
-    });
+    if let Ok(service_id) = resolve_service_id(profile) {
+        println!("Deploying to service {} (profile '{}')", service_id, profile);
+    }
+
+    let fastly_token = auth::resolve(args.fastly_token, auth::Credential::FastlyToken, profile)?;
 
     if !args.skip_build {
         // Build the application
@@ -562,7 +1728,7 @@ async fn deploy(args: DeployArgs) -> Result<(), Box<dyn std::error::Error>> {
     let deploy_output = Command::new("fastly")
         .arg("compute")
         .arg("deploy")
-        .env("FASTLY_API_TOKEN", fastly_token)
+        .env("FASTLY_API_TOKEN", fastly_token.expose_secret())
         .output()?;
     
     if !deploy_output.status.success() {
@@ -574,3 +1740,46 @@ async fn deploy(args: DeployArgs) -> Result<(), Box<dyn std::error::Error>> {
     println!("{}", style("✓ Application deployed successfully.").green());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_over_keeps_default_fields_the_override_leaves_unset() {
+        let default = Profile {
+            service_id: Some("svc-default".to_string()),
+            fmp4_aac_profile: Some("AAC-LC".to_string()),
+            fmp4_sample_rate: Some("44100".to_string()),
+            fmp4_channels: Some("2".to_string()),
+            fmp4_track_id: Some("1".to_string()),
+        };
+        let override_profile = Profile::default();
+
+        let merged = default.merged_over(&override_profile);
+        assert_eq!(merged.service_id.as_deref(), Some("svc-default"));
+        assert_eq!(merged.fmp4_sample_rate.as_deref(), Some("44100"));
+    }
+
+    #[test]
+    fn merged_over_takes_only_the_fields_the_override_sets() {
+        let default = Profile {
+            service_id: Some("svc-default".to_string()),
+            fmp4_aac_profile: Some("AAC-LC".to_string()),
+            fmp4_sample_rate: Some("44100".to_string()),
+            fmp4_channels: Some("2".to_string()),
+            fmp4_track_id: Some("1".to_string()),
+        };
+        let override_profile = Profile {
+            service_id: Some("svc-staging".to_string()),
+            fmp4_channels: Some("6".to_string()),
+            ..Profile::default()
+        };
+
+        let merged = default.merged_over(&override_profile);
+        assert_eq!(merged.service_id.as_deref(), Some("svc-staging"));
+        assert_eq!(merged.fmp4_channels.as_deref(), Some("6"));
+        assert_eq!(merged.fmp4_aac_profile.as_deref(), Some("AAC-LC"));
+        assert_eq!(merged.fmp4_sample_rate.as_deref(), Some("44100"));
+    }
+}
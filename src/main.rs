@@ -1,16 +1,18 @@
 use fastly::{
     kv_store::KVStore,
+    secret_store::SecretStore,
     error::Error,
     http::{header, Method, StatusCode},
     Request, Response,
 };
 use jsonwebtoken::{decode, DecodingKey, Validation};
-use rand::random;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use base64::{Engine as _, engine::general_purpose};
+use flate2::{read::DeflateDecoder, read::GzDecoder, write::DeflateEncoder, write::GzEncoder, Compression};
+use std::io::{Read, Write};
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -20,20 +22,39 @@ const PRIMARY_BACKEND: &str = "origin_1";
 /// The name of the backend for the watermarking service.
 const WATERMARKING_BACKEND: &str = "origin_2";
 
-/// The names of the KV stores and Edge Dictionaries used for configuration.
-const KV_STORE_SECRETS: &str = "secrets";  // KV store for secrets
-const DICTIONARY_API_KEYS: &str = "api_keys";
+/// The names of the stores and Edge Dictionaries used for configuration.
+/// Cryptographic material lives in the Secret Store (encrypted at rest, never echoed);
+/// the non-sensitive watermarking config stays in an ordinary KV store.
+const SECRET_STORE_SECRETS: &str = "secrets";
+const SECRET_STORE_API_KEYS: &str = "api_keys";
 const DICTIONARY_CONFIG: &str = "watermarking_config";
 
-const WATERMARK_PROBABILITY: f64 = 0.01; // 1% chance to watermark
 const MAX_AUDIO_SEGMENT_SIZE: usize = 500 * 1024; // 500 KB
 
+/// KV store tracking recent watermarking-backend failures for the circuit breaker.
+const CIRCUIT_BREAKER_STORE: &str = "watermarking_circuit_breaker";
+/// Failures within one window before the circuit trips.
+const CIRCUIT_BREAKER_THRESHOLD: u64 = 5;
+const CIRCUIT_BREAKER_WINDOW_SECS: u64 = 60;
+const CIRCUIT_BREAKER_COOLDOWN_SECS: u64 = 30;
+
+/// Attempts for a single segment's watermarking call, including the first try.
+const MAX_WATERMARK_ATTEMPTS: u32 = 3;
+const BASE_BACKOFF_MS: u64 = 100;
+
+/// Surfaces how a segment response was actually produced, for observability.
+const DISPOSITION_HEADER: &str = "X-Watermark-Disposition";
+
 /// Defines the structure for JWT claims.
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     #[serde(rename = "user_key")]
     user_key: String,
     exp: usize,
+    /// Token ID, checked against the `revoked:<jti>` revocation list.
+    jti: String,
+    /// Issued-at, checked against a per-user `revoked_before:<user_key>` cutoff.
+    iat: usize,
 }
 
 /// Main entry point for the Fastly Compute@Edge application.
@@ -73,8 +94,408 @@ fn derive_jwt_secret(api_key: &str, master_secret: &[u8]) -> Result<Vec<u8>, Err
     Ok(mac.finalize().into_bytes().to_vec())
 }
 
+/// Derives a per-viewer session secret from their `user_key` and the master secret, the same
+/// way `derive_jwt_secret` derives a per-key JWT secret. Used to key the forensic variant
+/// assignment so the bit sequence a viewer's segments carry is stable across requests but
+/// unpredictable without the master secret.
+fn derive_session_secret(user_key: &str, master_secret: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut mac = HmacSha256::new_from_slice(master_secret)
+        .map_err(|e| Error::msg(format!("Failed to create HMAC: {}", e)))?;
+
+    let message = format!("session_secret:{}", user_key);
+    mac.update(message.as_bytes());
+
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Assigns the forensic watermark variant ("A"/"B", as bit 0/1) for a given segment. Kept
+/// behind a trait so the HMAC-deterministic scheme shipped here can later be swapped for a
+/// Tardos-style probabilistic code (per-position bias `p_j`, accusation score `Σ`) without
+/// touching the request-handling path.
+trait VariantAssigner {
+    fn assign_bit(&self, session_secret: &[u8], segment_index: u64) -> Result<u8, Error>;
+}
+
+/// `bit = HMAC-SHA256(session_secret, segment_index_bytes)[0] & 1`. Deterministic per
+/// (session_secret, segment_index) pair, so a viewer sees the same variant on retries but a
+/// different, effectively random-looking bit at each segment index.
+struct HmacDeterministicAssigner;
+
+impl VariantAssigner for HmacDeterministicAssigner {
+    fn assign_bit(&self, session_secret: &[u8], segment_index: u64) -> Result<u8, Error> {
+        let mut mac = HmacSha256::new_from_slice(session_secret)
+            .map_err(|e| Error::msg(format!("Failed to create HMAC: {}", e)))?;
+        mac.update(&segment_index.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        Ok(digest[0] & 1)
+    }
+}
+
+/// Extracts the trailing integer from a segment path (e.g. `segment_00042.m4s` -> `42`),
+/// used as the stable per-segment index the variant assignment is keyed on. Returns `None`
+/// for paths with no trailing digits, e.g. init segments.
+fn extract_segment_index(path: &str) -> Option<u64> {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    let stem = filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(filename);
+    let digit_start = stem.rfind(|c: char| !c.is_ascii_digit()).map(|i| i + 1).unwrap_or(0);
+    let digits = &stem[digit_start..];
+    digits.parse::<u64>().ok()
+}
+
+/// Given a recovered bit vector from a leaked copy (one bit per `segment_indices` entry) and
+/// a table of candidate `user_key`s, recomputes each candidate's expected bits at the same
+/// indices and returns whichever matches best. This is offline forensic tooling for
+/// investigating a leak, not part of the request-handling path.
+#[allow(dead_code)]
+fn identify_leaker<'a>(
+    observed_bits: &[u8],
+    segment_indices: &[u64],
+    master_secret: &[u8],
+    candidate_user_keys: &'a [String],
+    assigner: &dyn VariantAssigner,
+) -> Result<Option<&'a str>, Error> {
+    let mut best: Option<(&str, usize)> = None;
+
+    for user_key in candidate_user_keys {
+        let session_secret = derive_session_secret(user_key, master_secret)?;
+        let matches = segment_indices.iter().zip(observed_bits.iter())
+            .filter(|(&index, &observed_bit)| {
+                assigner.assign_bit(&session_secret, index).map(|bit| bit == observed_bit).unwrap_or(false)
+            })
+            .count();
+
+        if best.map_or(true, |(_, best_matches)| matches > best_matches) {
+            best = Some((user_key.as_str(), matches));
+        }
+    }
+
+    Ok(best.map(|(user_key, _)| user_key))
+}
+
+/// Appends (or extends) `uri`'s query string with a `uk` token derived from `user_key`, so
+/// the downstream segment handler can tell which viewer's variant to request.
+fn append_token_to_uri(uri: &str, user_key: &str) -> String {
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{}{}uk={}", uri, separator, urlencoding::encode(user_key))
+}
+
+/// Rewrites an HLS playlist line by line: `#EXT` tags and blank lines pass through
+/// untouched, and every media segment URI gets the viewer's token appended.
+fn rewrite_hls_playlist(playlist: &str, user_key: &str) -> String {
+    let rewritten_lines: Vec<String> = playlist
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                line.to_string()
+            } else {
+                append_token_to_uri(trimmed, user_key)
+            }
+        })
+        .collect();
+
+    let mut rewritten = rewritten_lines.join("\n");
+    if playlist.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    rewritten
+}
+
+/// Rewrites a DASH manifest's `<SegmentTemplate media="...">`/`<SegmentURL media="...">`
+/// attributes in place, appending the viewer's token to each `media` value.
+fn rewrite_dash_manifest(manifest: &str, user_key: &str) -> String {
+    const MEDIA_ATTR: &str = "media=\"";
+
+    let mut rewritten = String::with_capacity(manifest.len());
+    let mut remaining = manifest;
+
+    while let Some(rel_pos) = remaining.find(MEDIA_ATTR) {
+        let attr_value_start = rel_pos + MEDIA_ATTR.len();
+        rewritten.push_str(&remaining[..attr_value_start]);
+
+        let after_quote = &remaining[attr_value_start..];
+        match after_quote.find('"') {
+            Some(end_rel) => {
+                let value = &after_quote[..end_rel];
+                rewritten.push_str(&append_token_to_uri(value, user_key));
+                rewritten.push('"');
+                remaining = &after_quote[end_rel + 1..];
+            }
+            None => {
+                // Unterminated attribute — leave the rest of the document untouched.
+                rewritten.push_str(after_quote);
+                remaining = "";
+            }
+        }
+    }
+    rewritten.push_str(remaining);
+    rewritten
+}
+
+/// Which manifest format a `Path::Manifest` request is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ManifestFormat {
+    Hls,
+    Dash,
+}
+
+/// The parsed shape of an incoming request path, so routing is a single `match` instead of
+/// a chain of `.ends_with` checks re-examining the same string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Path {
+    Manifest(ManifestFormat),
+    InitSegment,
+    MediaSegment { index: u64 },
+    Health,
+    Unknown,
+}
+
+impl Path {
+    /// Classifies `req`'s path. Unauthenticated health checks are recognized here too, ahead
+    /// of JWT verification, so `handle_request` can short-circuit before any Secret Store
+    /// lookups.
+    fn from_request(req: &Request) -> Path {
+        let path = req.get_path();
+        if path == "/health" || path == "/healthz" {
+            return Path::Health;
+        }
+        if path.ends_with(".m3u8") {
+            return Path::Manifest(ManifestFormat::Hls);
+        }
+        if path.ends_with(".mpd") {
+            return Path::Manifest(ManifestFormat::Dash);
+        }
+        if path.ends_with(".cmfv") {
+            return Path::InitSegment;
+        }
+        match extract_segment_index(path) {
+            Some(index) => Path::MediaSegment { index },
+            None => Path::Unknown,
+        }
+    }
+}
+
+/// Parses a `Range: bytes=start-end` header value against a resource of `len` bytes. Only
+/// the single-range form is supported — media players don't send multipart ranges. Returns
+/// `Err(())` for a range that can't be satisfied against `len` (e.g. a start past the end).
+fn parse_range(value: &str, len: usize) -> Result<(usize, usize), ()> {
+    let spec = value.strip_prefix("bytes=").ok_or(())?;
+    let (start_str, end_str) = spec.split_once('-').ok_or(())?;
+
+    if len == 0 {
+        return Err(());
+    }
+
+    let (start, end) = if start_str.is_empty() {
+        // Suffix range (e.g. "bytes=-500"): the last `end_str` bytes.
+        let suffix_len: usize = end_str.parse().map_err(|_| ())?;
+        let suffix_len = suffix_len.min(len);
+        (len - suffix_len, len - 1)
+    } else {
+        let start: usize = start_str.parse().map_err(|_| ())?;
+        let end = match end_str.is_empty() {
+            true => len - 1,
+            false => end_str.parse().map_err(|_| ())?,
+        };
+        (start, end)
+    };
+
+    if start >= len || start > end {
+        return Err(());
+    }
+    Ok((start, end.min(len - 1)))
+}
+
+/// Builds the response for `body`, honoring the request's `Range` header if present. Ranges
+/// are resolved against `body`'s length — for watermarked segments, that's the watermarked
+/// output, not the original segment's length, since watermarking can shift byte offsets.
+fn segment_response(req: &Request, body: Vec<u8>, content_type: &str) -> Response {
+    let len = body.len();
+    let range = match req.get_header_str(header::RANGE) {
+        Some(value) => Some(parse_range(value, len)),
+        None => None,
+    };
+
+    match range {
+        None => Response::from_status(StatusCode::OK)
+            .with_header(header::CONTENT_TYPE, content_type)
+            .with_header(header::ACCEPT_RANGES, "bytes")
+            .with_body(body),
+        Some(Err(())) => Response::from_status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .with_header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .with_header(header::ACCEPT_RANGES, "bytes"),
+        Some(Ok((start, end))) => Response::from_status(StatusCode::PARTIAL_CONTENT)
+            .with_header(header::CONTENT_TYPE, content_type)
+            .with_header(header::ACCEPT_RANGES, "bytes")
+            .with_header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+            .with_body(body[start..=end].to_vec()),
+    }
+}
+
+/// A content-coding this service knows how to produce, in the order it prefers them — `br`
+/// edges out `gzip` on ratio, `gzip` edges out `deflate` on client support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the best encoding this service can produce out of the client's `Accept-Encoding`
+    /// value, skipping anything the client explicitly disallowed with `;q=0`.
+    fn negotiate(accept_encoding: &str) -> Option<ContentEncoding> {
+        let accepted: Vec<&str> = accept_encoding
+            .split(',')
+            .filter_map(|part| {
+                let (coding, params) = part.trim().split_once(';').unwrap_or((part.trim(), ""));
+                let disallowed = params.trim().eq_ignore_ascii_case("q=0") || params.trim() == "q=0.0";
+                if disallowed { None } else { Some(coding.trim()) }
+            })
+            .collect();
+
+        [ContentEncoding::Brotli, ContentEncoding::Gzip, ContentEncoding::Deflate]
+            .into_iter()
+            .find(|encoding| accepted.iter().any(|a| a.eq_ignore_ascii_case(encoding.as_str())))
+    }
+}
+
+/// Compresses `body` with `encoding`. Returns `None` on an encoder failure, in which case the
+/// caller should fall back to serving the body uncompressed rather than fail the request.
+fn compress_body(body: &[u8], encoding: ContentEncoding) -> Option<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body).ok()?;
+            encoder.finish().ok()
+        }
+        ContentEncoding::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body).ok()?;
+            encoder.finish().ok()
+        }
+        ContentEncoding::Brotli => {
+            let mut out = Vec::new();
+            let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+            writer.write_all(body).ok()?;
+            drop(writer);
+            Some(out)
+        }
+    }
+}
+
+/// Decompresses `body` per a `Content-Encoding` header value. Returns `None` for an unknown
+/// encoding or a decode failure, in which case the caller should fall back to the raw bytes.
+fn decompress_body(body: &[u8], encoding: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "gzip" => GzDecoder::new(body).read_to_end(&mut out).ok()?,
+        "deflate" => DeflateDecoder::new(body).read_to_end(&mut out).ok()?,
+        "br" => brotli::Decompressor::new(body, 4096).read_to_end(&mut out).ok()?,
+        _ => return None,
+    };
+    Some(out)
+}
+
+fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Backoff delay before retry `attempt` (1-based, the attempt that just failed): doubles each
+/// time, plus jitter so retries from concurrent requests don't all land on the backend at once.
+fn jittered_backoff_ms(attempt: u32) -> u64 {
+    let base = BASE_BACKOFF_MS * 2u64.pow(attempt.saturating_sub(1));
+    let jitter_seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    base + (jitter_seed % base.max(1))
+}
+
+/// Only 502/503/504 and transport errors are worth retrying — 403/413 mean the request itself
+/// is wrong and trying again won't help.
+fn is_retryable_watermark_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 502 | 503 | 504)
+}
+
+/// Sets `DISPOSITION_HEADER` on `response` so operators can tell from response headers alone
+/// whether a segment was watermarked, served unwatermarked as a fallback, or skipped entirely
+/// because the circuit breaker was open.
+fn with_disposition(mut response: Response, disposition: &str) -> Response {
+    response.set_header(DISPOSITION_HEADER, disposition);
+    response
+}
+
+/// Tracks recent watermarking-backend failures in a KV store and trips a cooldown once
+/// `CIRCUIT_BREAKER_THRESHOLD` failures land inside one rolling window, so a dead backend gets
+/// a break instead of every segment request hammering it with retries.
+struct CircuitBreaker {
+    store: KVStore,
+}
+
+impl CircuitBreaker {
+    fn open(name: &str) -> Result<Option<CircuitBreaker>, Error> {
+        Ok(KVStore::open(name)?.map(|store| CircuitBreaker { store }))
+    }
+
+    fn read_u64(&self, key: &str) -> u64 {
+        self.store
+            .lookup(key)
+            .ok()
+            .flatten()
+            .and_then(|body| String::from_utf8_lossy(&body.into_bytes()).trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// True while a previously-tripped cooldown is still in effect.
+    fn is_open(&self) -> bool {
+        self.read_u64("opened_until") > now_unix()
+    }
+
+    fn record_failure(&self) {
+        let now = now_unix();
+        let window_start = self.read_u64("window_start");
+        let (window_start, failures) =
+            if window_start == 0 || now.saturating_sub(window_start) > CIRCUIT_BREAKER_WINDOW_SECS {
+                (now, 1)
+            } else {
+                (window_start, self.read_u64("failures") + 1)
+            };
+
+        let _ = self.store.insert("window_start", window_start.to_string());
+        let _ = self.store.insert("failures", failures.to_string());
+
+        if failures >= CIRCUIT_BREAKER_THRESHOLD {
+            println!("WATERMARKING: Circuit breaker tripped after {} failures", failures);
+            let _ = self.store.insert("opened_until", (now + CIRCUIT_BREAKER_COOLDOWN_SECS).to_string());
+        }
+    }
+
+    fn record_success(&self) {
+        let _ = self.store.insert("failures", "0".to_string());
+    }
+}
+
 /// Handles the main logic of the application: authentication, routing, and watermarking.
 fn handle_request(mut req: Request) -> Result<Response, Error> {
+    let route = Path::from_request(&req);
+
+    // Health checks don't carry a viewer token and don't need one.
+    if route == Path::Health {
+        return Ok(Response::from_status(StatusCode::OK).with_body_text_plain("ok\n"));
+    }
+
     // --- JWT Verification ---
     // Token can be provided in 'Authorization: Bearer <token>' header or 'token' query param.
     let token_opt = req.get_header_str("Authorization")
@@ -95,19 +516,19 @@ fn handle_request(mut req: Request) -> Result<Response, Error> {
         }
     };
     
-    // Get the master secret key from the KV store
-    let secrets_kv = KVStore::open(KV_STORE_SECRETS)?.expect("secrets KV store not found");
-    let secret_key_hex = match secrets_kv.lookup("SECRET_KEY_HEX")? {
-        Some(body) => String::from_utf8_lossy(&body.into_bytes()).to_string(),
+    // Get the master secret key from the Secret Store.
+    let secrets_store = SecretStore::open(SECRET_STORE_SECRETS)?.expect("secrets secret store not found");
+    let secret_key_hex = match secrets_store.get("SECRET_KEY_HEX") {
+        Some(secret) => String::from_utf8_lossy(secret.plaintext()).to_string(),
         None => {
-            println!("SECRET_KEY_HEX not found in KV store");
+            println!("SECRET_KEY_HEX not found in secret store");
             return Ok(Response::from_status(StatusCode::INTERNAL_SERVER_ERROR)
                 .with_body_text_plain("Server configuration error.\n"));
         }
     };
 
     if secret_key_hex.trim().is_empty() {
-        println!("SECRET_KEY_HEX is empty in KV store");
+        println!("SECRET_KEY_HEX is empty in secret store");
         return Ok(Response::from_status(StatusCode::INTERNAL_SERVER_ERROR)
             .with_body_text_plain("Server configuration error.\n"));
     }
@@ -122,19 +543,19 @@ fn handle_request(mut req: Request) -> Result<Response, Error> {
         }
     };
 
-    // Get the API key from the KV store - this should be the same API key used to sign the JWT
-    let api_keys = KVStore::open(DICTIONARY_API_KEYS)?.expect("api_keys KVStore not found");
-    let service_api_key = match api_keys.lookup("service_api_key")? {
-        Some(body) => String::from_utf8_lossy(&body.into_bytes()).to_string(),
+    // Get the API key from the Secret Store - this should be the same API key used to sign the JWT
+    let api_keys_store = SecretStore::open(SECRET_STORE_API_KEYS)?.expect("api_keys secret store not found");
+    let service_api_key = match api_keys_store.get("service_api_key") {
+        Some(secret) => String::from_utf8_lossy(secret.plaintext()).to_string(),
         None => {
-            println!("service_api_key not found in KV store");
+            println!("service_api_key not found in secret store");
             return Ok(Response::from_status(StatusCode::INTERNAL_SERVER_ERROR)
                 .with_body_text_plain("Server configuration error.\n"));
         }
     };
 
     if service_api_key.trim().is_empty() {
-        println!("service_api_key is empty in KV store");
+        println!("service_api_key is empty in secret store");
         return Ok(Response::from_status(StatusCode::INTERNAL_SERVER_ERROR)
             .with_body_text_plain("Server configuration error.\n"));
     }
@@ -178,190 +599,437 @@ fn handle_request(mut req: Request) -> Result<Response, Error> {
                 .with_body_text_plain("Invalid JWT.\n"));
         }
     };
-    
-    // --- Routing Logic ---
-    let path = req.get_path().to_string();
 
-    // Serve manifest files directly from the primary origin.
-    if path.ends_with(".m3u8") || path.ends_with(".mpd") || path.ends_with(".cmfv") {
-        // Create a clean request without authentication headers for the origin
-        let mut clean_req = Request::new(req.get_method().clone(), req.get_url().clone());
-        let body = req.take_body_bytes();
-        if !body.is_empty() {
-            clean_req = clean_req.with_body(body);
+    // --- Revocation Check ---
+    // A valid signature only proves the token was legitimately issued, not that it's still
+    // good — check the revocation list so a leaked token can be killed before `exp` without
+    // rotating the master secret.
+    if secrets_store.get(&format!("revoked:{}", claims.jti)).is_some() {
+        println!("Token {} is revoked", claims.jti);
+        return Ok(Response::from_status(StatusCode::UNAUTHORIZED)
+            .with_body_text_plain("Token has been revoked.\n"));
+    }
+
+    // Bulk per-user revocation: everything issued before this cutoff is dead, so an operator
+    // can invalidate every token issued to a compromised account in one write.
+    if let Some(secret) = secrets_store.get(&format!("revoked_before:{}", claims.user_key)) {
+        let cutoff: usize = String::from_utf8_lossy(secret.plaintext()).trim().parse().unwrap_or(0);
+        if claims.iat < cutoff {
+            println!("Token for user {} predates revoked_before cutoff", claims.user_key);
+            return Ok(Response::from_status(StatusCode::UNAUTHORIZED)
+                .with_body_text_plain("Token has been revoked.\n"));
         }
-        return Ok(clean_req.send(PRIMARY_BACKEND)?);
     }
 
-    // For segment requests, decide whether to watermark.
-    let should_watermark = random::<f64>() > (1.0 - WATERMARK_PROBABILITY);
+    // --- Routing Logic ---
+    let path = req.get_path().to_string();
+
+    match route {
+        // Serve HLS/DASH manifests with per-viewer segment URLs injected, so variant selection
+        // happens at manifest time and the resulting segment URLs are still CDN-cacheable per
+        // variant, instead of forcing every segment through edge compute.
+        Path::Manifest(format) => {
+            // Create a clean request without authentication headers for the origin
+            let mut clean_req = Request::new(req.get_method().clone(), req.get_url().clone());
+            let body = req.take_body_bytes();
+            if !body.is_empty() {
+                clean_req = clean_req.with_body(body);
+            }
+            let mut origin_resp = clean_req.send(PRIMARY_BACKEND)?;
+            if !origin_resp.get_status().is_success() {
+                return Ok(origin_resp);
+            }
 
-    if should_watermark {
-        // --- Watermarking Path ---
-        println!("Watermarking segment: {}", path);
+            let content_type = origin_resp.get_header(header::CONTENT_TYPE).cloned();
+            let cache_control = origin_resp.get_header(header::CACHE_CONTROL).cloned();
+            let origin_encoding = origin_resp
+                .get_header(header::CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let manifest_bytes = origin_resp.into_body_bytes();
+            // The origin may have already compressed its response — decompress before rewriting
+            // so we're rewriting plain text, not garbling compressed bytes.
+            let manifest_bytes = origin_encoding
+                .as_deref()
+                .and_then(|encoding| decompress_body(&manifest_bytes, encoding))
+                .unwrap_or(manifest_bytes);
+            let manifest_body = String::from_utf8_lossy(&manifest_bytes).into_owned();
 
-        // 1. Fetch the original segment from the primary origin.
-        let mut clean_segment_req = Request::new(req.get_method().clone(), req.get_url().clone());
-        let body = req.clone_with_body().take_body_bytes();
-        if !body.is_empty() {
-            clean_segment_req = clean_segment_req.with_body(body);
-        }
-        let original_segment_resp = clean_segment_req.send(PRIMARY_BACKEND)?;
-        if !original_segment_resp.get_status().is_success() {
-            println!("WATERMARKING: Failed to fetch original segment from primary backend.");
-            return Ok(original_segment_resp); // Pass through error from origin
-        }
-        let segment_body = original_segment_resp.into_body();
-        let segment_body_bytes = segment_body.into_bytes(); // Store original bytes for fallback
-
-        // Skip watermarking if the segment is too large (likely video).
-        if segment_body_bytes.len() > MAX_AUDIO_SEGMENT_SIZE {
-            println!(
-                "WATERMARKING: Skipping segment (too large: {} bytes): {}",
-                segment_body_bytes.len(),
-                path
-            );
-            return Ok(Response::from_status(StatusCode::OK).with_body(segment_body_bytes));
-        }
+            let rewritten = match format {
+                ManifestFormat::Hls => rewrite_hls_playlist(&manifest_body, &claims.user_key),
+                ManifestFormat::Dash => rewrite_dash_manifest(&manifest_body, &claims.user_key),
+            };
 
-        // 2. Prepare a new request to the watermarking service.
-        let mut watermark_url = req.get_url().clone();
-        // Add user_key query parameter
-        let mut query_pairs: Vec<(String, String)> = watermark_url.query_pairs().into_owned().collect();
-        query_pairs.push(("user_key".to_string(), claims.user_key.clone()));
-        let query_string = query_pairs.iter()
-            .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
-            .collect::<Vec<_>>()
-            .join("&");
-        watermark_url.set_query(Some(&query_string));
-        
-        println!("WATERMARKING: Sending segment to watermarking service for path: {}", path);
-        println!("WATERMARKING: Request URL: {}", watermark_url);
-        println!("WATERMARKING: Binary payload size: {} bytes", segment_body_bytes.len());
-        println!("WATERMARKING: User key: {}", claims.user_key);
-        
-        // Send raw binary data instead of JSON with base64
-        let mut watermark_req = Request::new(Method::POST, watermark_url)
-            .with_body(segment_body_bytes.clone())
-            .with_header("Content-Type", "application/octet-stream");
-        
-        // Add API key for watermarking service authentication
-        let api_keys = KVStore::open(DICTIONARY_API_KEYS)?.expect("api_keys KVStore not found");
-        let service_api_key = match api_keys.lookup("service_api_key")? {
-            Some(body) => String::from_utf8_lossy(&body.into_bytes()).to_string(),
-            None => String::new()
-        };
-        
-        // Check for any potential whitespace or special characters
-        if service_api_key.contains(char::is_whitespace) {
-            println!("WATERMARKING: WARNING - API key contains whitespace!");
-        }
-        
-        if !service_api_key.is_empty() {
-            println!("WATERMARKING: Adding API key to request: {}", &service_api_key[..std::cmp::min(service_api_key.len(), 30)]);
-            watermark_req.set_header("X-API-Key", service_api_key.trim());
-            
-            // Add explicit Host header to ensure correct routing
-            watermark_req.set_header("Host", "api.stegawave.com");
-            println!("WATERMARKING: Added explicit Host header: api.stegawave.com");
-        } else {
-            println!("WATERMARKING: No API key found");
-        }
-        
-        // Add encoding configuration as headers to the watermarking request.
-        let config = KVStore::open(DICTIONARY_CONFIG)?.expect("watermarking_config KVStore not found");
+            // Manifests are plain text, so it's worth compressing them for clients that support it.
+            let negotiated = req.get_header_str(header::ACCEPT_ENCODING)
+                .and_then(ContentEncoding::negotiate)
+                .and_then(|encoding| compress_body(rewritten.as_bytes(), encoding).map(|body| (encoding, body)));
 
-        if let Some(body) = config.lookup("FMP4_AAC_PROFILE")? {
-            watermark_req.set_header("FMP4_AAC_PROFILE", String::from_utf8_lossy(&body.into_bytes()).to_string());
-        }
-        if let Some(body) = config.lookup("FMP4_SAMPLE_RATE")? {
-            watermark_req.set_header("FMP4_SAMPLE_RATE", String::from_utf8_lossy(&body.into_bytes()).to_string());
-        }
-        if let Some(body) = config.lookup("FMP4_CHANNELS")? {
-            watermark_req.set_header("FMP4_CHANNELS", String::from_utf8_lossy(&body.into_bytes()).to_string());
-        }
-        if let Some(body) = config.lookup("FMP4_TRACK_ID")? {
-            watermark_req.set_header("FMP4_TRACK_ID", String::from_utf8_lossy(&body.into_bytes()).to_string());
+            let mut response = match negotiated {
+                Some((encoding, compressed)) => Response::from_status(StatusCode::OK)
+                    .with_header(header::CONTENT_ENCODING, encoding.as_str())
+                    .with_header(header::CONTENT_LENGTH, compressed.len().to_string())
+                    .with_body(compressed),
+                None => Response::from_status(StatusCode::OK).with_body(rewritten),
+            };
+            // Cache-Control is forwarded from origin below, so these responses are meant to be
+            // shared-cacheable — Vary must be set on every branch or a cache can serve a
+            // compressed response to a client that didn't ask for one, or vice versa.
+            response.set_header(header::VARY, "Accept-Encoding");
+            if let Some(value) = content_type {
+                response.set_header(header::CONTENT_TYPE, value);
+            }
+            if let Some(value) = cache_control {
+                response.set_header(header::CACHE_CONTROL, value);
+            }
+            Ok(response)
         }
 
-        // 3. Send the segment to the watermarking service.
-        println!("WATERMARKING: Sending request to backend: {}", WATERMARKING_BACKEND);
-        let mut watermarked_resp = match watermark_req.send(WATERMARKING_BACKEND) {
-            Ok(resp) => resp,
-            Err(e) => {
-                println!("WATERMARKING: Failed to send request to backend: {}", e);
-                println!("WATERMARKING: Falling back to original content due to backend error");
-                return Ok(Response::from_status(StatusCode::OK)
-                    .with_header("Content-Type", "video/mp4")
-                    .with_body(segment_body_bytes));
+        // CMAF init segments have no per-segment URL to rewrite, so they pass through verbatim.
+        // The origin serves these directly, so forward any Range header and let it answer with
+        // its own 206/416 rather than re-deriving that here.
+        Path::InitSegment => {
+            let mut clean_req = Request::new(req.get_method().clone(), req.get_url().clone());
+            if let Some(range) = req.get_header(header::RANGE).cloned() {
+                clean_req.set_header(header::RANGE, range);
             }
-        };
-        
-        println!("WATERMARKING: Response status: {}", watermarked_resp.get_status());
-        let headers: Vec<_> = watermarked_resp.get_headers().collect();
-        println!("WATERMARKING: Response headers count: {}", headers.len());
-        for (name, value) in &headers {
-            println!("  Response header {}: {:?}", name, value);
+            let body = req.take_body_bytes();
+            if !body.is_empty() {
+                clean_req = clean_req.with_body(body);
+            }
+            Ok(clean_req.send(PRIMARY_BACKEND)?)
         }
-        
-        // Check if response has content first, regardless of status code
-        let response_body = watermarked_resp.clone_with_body().into_body_bytes();
-        if response_body.is_empty() {
-            println!("WATERMARKING: Service returned empty response (status: {}), falling back to original content", watermarked_resp.get_status());
-            // Return original unwatermarked content
-            Ok(Response::from_status(StatusCode::OK)
-                .with_header("Content-Type", "video/mp4")
-                .with_body(segment_body_bytes))
-        } else if watermarked_resp.get_status().is_success() {
-            println!("WATERMARKING: Service returned watermarked content ({} bytes)", response_body.len());
-            // Return the watermarked response with original headers
-            let mut response = Response::from_status(watermarked_resp.get_status())
-                .with_body(response_body);
-            
-            // Copy headers from the watermarked response
-            for (name, value) in watermarked_resp.get_headers() {
-                response.set_header(name, value);
+
+        // Deterministically assign an A/B variant per segment index so the sequence of
+        // variants a viewer's copy carries encodes their identity end-to-end, rather than
+        // randomly watermarking a small fraction of segments.
+        Path::MediaSegment { index: segment_index } => {
+            println!("Watermarking segment: {}", path);
+
+            let session_secret = derive_session_secret(&claims.user_key, &master_secret)?;
+            let variant_bit = HmacDeterministicAssigner.assign_bit(&session_secret, segment_index)?;
+            let variant = if variant_bit == 1 { "B" } else { "A" };
+
+            // 1. Fetch the original segment from the primary origin.
+            let mut clean_segment_req = Request::new(req.get_method().clone(), req.get_url().clone());
+            let body = req.clone_with_body().take_body_bytes();
+            if !body.is_empty() {
+                clean_segment_req = clean_segment_req.with_body(body);
             }
-            
-            Ok(response)
-        } else {
-            let response_body = watermarked_resp.clone_with_body().into_body_str();
-            let status = watermarked_resp.get_status();
-            println!("WATERMARKING: Error response status: {}", status);
-            println!("WATERMARKING: Error response body: {}", response_body);
-            
-            // Provide specific guidance based on status code
-            match status.as_u16() {
-                403 => {
-                    println!("WATERMARKING: 403 Forbidden ");
-                    println!("Verify API key is correct and active");
-                },
-                413 => {
-                    println!("WATERMARKING: 413 Payload Too Large - Request body too large for API Gateway");
-                    println!("  - Consider reducing segment size");
-                },
-                502 | 503 | 504 => {
-                    println!("WATERMARKING: {} - Backend service issue:", status);
-                },
-                _ => {
-                    println!("WATERMARKING: Unexpected error status: {}", status);
+            let original_segment_resp = clean_segment_req.send(PRIMARY_BACKEND)?;
+            if !original_segment_resp.get_status().is_success() {
+                println!("WATERMARKING: Failed to fetch original segment from primary backend.");
+                return Ok(original_segment_resp); // Pass through error from origin
+            }
+            let origin_encoding = original_segment_resp
+                .get_header(header::CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let segment_body = original_segment_resp.into_body();
+            let segment_body_bytes = segment_body.into_bytes(); // Store original bytes for fallback
+            // Decompress before watermarking so the watermarking backend always receives raw audio.
+            let segment_body_bytes = origin_encoding
+                .as_deref()
+                .and_then(|encoding| decompress_body(&segment_body_bytes, encoding))
+                .unwrap_or(segment_body_bytes);
+
+            // Skip watermarking if the segment is too large (likely video).
+            if segment_body_bytes.len() > MAX_AUDIO_SEGMENT_SIZE {
+                println!(
+                    "WATERMARKING: Skipping segment (too large: {} bytes): {}",
+                    segment_body_bytes.len(),
+                    path
+                );
+                return Ok(segment_response(&req, segment_body_bytes, "video/mp4"));
+            }
+
+            // 2. Prepare a new request to the watermarking service.
+            let mut watermark_url = req.get_url().clone();
+            // Add user_key query parameter
+            let mut query_pairs: Vec<(String, String)> = watermark_url.query_pairs().into_owned().collect();
+            query_pairs.push(("user_key".to_string(), claims.user_key.clone()));
+            let query_string = query_pairs.iter()
+                .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+                .collect::<Vec<_>>()
+                .join("&");
+            watermark_url.set_query(Some(&query_string));
+
+            println!("WATERMARKING: Sending segment to watermarking service for path: {}", path);
+            println!("WATERMARKING: Request URL: {}", watermark_url);
+            println!("WATERMARKING: Binary payload size: {} bytes", segment_body_bytes.len());
+            println!("WATERMARKING: User key: {}", claims.user_key);
+            println!("WATERMARKING: Segment index {}, assigned variant {}", segment_index, variant);
+
+            // Add API key for watermarking service authentication
+            let api_keys_store = SecretStore::open(SECRET_STORE_API_KEYS)?.expect("api_keys secret store not found");
+            let service_api_key = match api_keys_store.get("service_api_key") {
+                Some(secret) => String::from_utf8_lossy(secret.plaintext()).to_string(),
+                None => String::new()
+            };
+
+            // Check for any potential whitespace or special characters
+            if service_api_key.contains(char::is_whitespace) {
+                println!("WATERMARKING: WARNING - API key contains whitespace!");
+            }
+
+            // Add encoding configuration as headers to the watermarking request.
+            let config = KVStore::open(DICTIONARY_CONFIG)?.expect("watermarking_config KVStore not found");
+            let mut fmp4_headers: Vec<(&str, String)> = Vec::new();
+            for key in ["FMP4_AAC_PROFILE", "FMP4_SAMPLE_RATE", "FMP4_CHANNELS", "FMP4_TRACK_ID"] {
+                if let Some(body) = config.lookup(key)? {
+                    fmp4_headers.push((key, String::from_utf8_lossy(&body.into_bytes()).to_string()));
                 }
             }
-            
-            println!("WATERMARKING: Service failed, falling back to original content");
-            // Return original unwatermarked content on error
-            Ok(Response::from_status(StatusCode::OK)
-                .with_header("Content-Type", "video/mp4")
-                .with_body(segment_body_bytes))
-        }
-    } else {
-        // --- Standard Path (No Watermarking) ---
-        // Create a clean request without authentication headers for the origin
-        let mut clean_req = Request::new(req.get_method().clone(), req.get_url().clone());
-        let body = req.take_body_bytes();
-        if !body.is_empty() {
-            clean_req = clean_req.with_body(body);
+
+            // Builds a fresh request for each attempt — `Request` is consumed by `.send()`.
+            let build_watermark_request = || {
+                let mut watermark_req = Request::new(Method::POST, watermark_url.clone())
+                    .with_body(segment_body_bytes.clone())
+                    .with_header("Content-Type", "application/octet-stream")
+                    .with_header("X-Watermark-Variant", variant);
+
+                if !service_api_key.is_empty() {
+                    watermark_req.set_header("X-API-Key", service_api_key.trim());
+                    // Add explicit Host header to ensure correct routing
+                    watermark_req.set_header("Host", "api.stegawave.com");
+                }
+
+                for (key, value) in &fmp4_headers {
+                    watermark_req.set_header(*key, value.clone());
+                }
+                watermark_req
+            };
+
+            // 3. Send the segment to the watermarking service, retrying transient (502/503/504,
+            // transport) failures with exponential backoff, unless the circuit breaker is open.
+            let breaker = CircuitBreaker::open(CIRCUIT_BREAKER_STORE)?;
+
+            if breaker.as_ref().map_or(false, CircuitBreaker::is_open) {
+                println!("WATERMARKING: Circuit breaker open, serving original content without watermarking");
+                return Ok(with_disposition(
+                    segment_response(&req, segment_body_bytes, "video/mp4"),
+                    "circuit-open",
+                ));
+            }
+
+            println!("WATERMARKING: Sending request to backend: {}", WATERMARKING_BACKEND);
+            let mut watermarked_resp = None;
+            for attempt in 1..=MAX_WATERMARK_ATTEMPTS {
+                match build_watermark_request().send(WATERMARKING_BACKEND) {
+                    Ok(resp) => {
+                        let status = resp.get_status();
+                        // A 200 with an empty body is as useless as a 5xx — treat it the same
+                        // way so a degraded backend still gets retried and counted against the
+                        // circuit breaker instead of being accepted on the first attempt forever.
+                        let empty_success = status.is_success() && resp.clone_with_body().into_body_bytes().is_empty();
+                        if !empty_success && (status.is_success() || !is_retryable_watermark_status(status)) {
+                            watermarked_resp = Some(resp);
+                            break;
+                        }
+                        if empty_success {
+                            println!("WATERMARKING: attempt {} got {} with an empty body", attempt, status);
+                        } else {
+                            println!("WATERMARKING: attempt {} got {}", attempt, status);
+                        }
+                        if let Some(breaker) = &breaker {
+                            breaker.record_failure();
+                        }
+                        if attempt < MAX_WATERMARK_ATTEMPTS {
+                            std::thread::sleep(std::time::Duration::from_millis(jittered_backoff_ms(attempt)));
+                        }
+                    }
+                    Err(e) => {
+                        println!("WATERMARKING: attempt {} transport error: {}", attempt, e);
+                        if let Some(breaker) = &breaker {
+                            breaker.record_failure();
+                        }
+                        if attempt < MAX_WATERMARK_ATTEMPTS {
+                            std::thread::sleep(std::time::Duration::from_millis(jittered_backoff_ms(attempt)));
+                        }
+                    }
+                }
+            }
+
+            let mut watermarked_resp = match watermarked_resp {
+                Some(resp) => resp,
+                None => {
+                    println!("WATERMARKING: All {} attempts failed, falling back to original content", MAX_WATERMARK_ATTEMPTS);
+                    return Ok(with_disposition(
+                        segment_response(&req, segment_body_bytes, "video/mp4"),
+                        "fallback",
+                    ));
+                }
+            };
+
+            println!("WATERMARKING: Response status: {}", watermarked_resp.get_status());
+
+            // Check if response has content first, regardless of status code
+            let response_body = watermarked_resp.clone_with_body().into_body_bytes();
+            if response_body.is_empty() {
+                println!("WATERMARKING: Service returned empty response (status: {}), falling back to original content", watermarked_resp.get_status());
+                // Return original unwatermarked content
+                Ok(with_disposition(segment_response(&req, segment_body_bytes, "video/mp4"), "fallback"))
+            } else if watermarked_resp.get_status().is_success() {
+                println!("WATERMARKING: Service returned watermarked content ({} bytes)", response_body.len());
+                if let Some(breaker) = &breaker {
+                    breaker.record_success();
+                }
+
+                let content_type = watermarked_resp
+                    .get_header(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("video/mp4")
+                    .to_string();
+                // The watermarking backend may have compressed its response — decompress so
+                // segment_response always serves the final watermarked media uncompressed.
+                let response_body = watermarked_resp
+                    .get_header(header::CONTENT_ENCODING)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|encoding| decompress_body(&response_body, encoding))
+                    .unwrap_or(response_body);
+                let mut response = with_disposition(segment_response(&req, response_body, &content_type), "watermarked");
+
+                // Copy any other headers from the watermarked response.
+                for (name, value) in watermarked_resp.get_headers() {
+                    if *name != header::CONTENT_TYPE
+                        && *name != header::CONTENT_LENGTH
+                        && *name != header::CONTENT_ENCODING
+                    {
+                        response.set_header(name, value);
+                    }
+                }
+
+                Ok(response)
+            } else {
+                let response_body = watermarked_resp.clone_with_body().into_body_str();
+                let status = watermarked_resp.get_status();
+                println!("WATERMARKING: Error response status: {}", status);
+                println!("WATERMARKING: Error response body: {}", response_body);
+
+                // Provide specific guidance based on status code
+                match status.as_u16() {
+                    403 => {
+                        println!("WATERMARKING: 403 Forbidden ");
+                        println!("Verify API key is correct and active");
+                    },
+                    413 => {
+                        println!("WATERMARKING: 413 Payload Too Large - Request body too large for API Gateway");
+                        println!("  - Consider reducing segment size");
+                    },
+                    502 | 503 | 504 => {
+                        println!("WATERMARKING: {} - Backend service issue:", status);
+                    },
+                    _ => {
+                        println!("WATERMARKING: Unexpected error status: {}", status);
+                    }
+                }
+
+                println!("WATERMARKING: Service failed, falling back to original content");
+                // Return original unwatermarked content on error
+                Ok(with_disposition(segment_response(&req, segment_body_bytes, "video/mp4"), "fallback"))
+            }
         }
-        Ok(clean_req.send(PRIMARY_BACKEND)?)
+
+        Path::Health => unreachable!("Path::Health is handled before JWT verification"),
+
+        // Nothing matched a known route shape — rather than silently proxying to origin,
+        // fail closed with a clean 404.
+        Path::Unknown => Ok(Response::from_status(StatusCode::NOT_FOUND)
+            .with_body_text_plain("Not found.\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_segment_index_reads_trailing_digits() {
+        assert_eq!(extract_segment_index("segment_00042.m4s"), Some(42));
+        assert_eq!(extract_segment_index("/a/b/segment_7.m4s"), Some(7));
+    }
+
+    #[test]
+    fn extract_segment_index_none_without_trailing_digits() {
+        assert_eq!(extract_segment_index("init.cmfv"), None);
+        assert_eq!(extract_segment_index("segment.m4s"), None);
+    }
+
+    #[test]
+    fn rewrite_hls_playlist_appends_token_to_segment_uris_only() {
+        let playlist = "#EXTM3U\n#EXTINF:6.0,\nsegment_1.m4s\n\nsegment_2.m4s\n";
+        let rewritten = rewrite_hls_playlist(playlist, "viewer1");
+        assert_eq!(
+            rewritten,
+            "#EXTM3U\n#EXTINF:6.0,\nsegment_1.m4s?uk=viewer1\n\nsegment_2.m4s?uk=viewer1\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_hls_playlist_preserves_trailing_newline_absence() {
+        let playlist = "#EXTM3U\nsegment_1.m4s";
+        let rewritten = rewrite_hls_playlist(playlist, "viewer1");
+        assert_eq!(rewritten, "#EXTM3U\nsegment_1.m4s?uk=viewer1");
+    }
+
+    #[test]
+    fn rewrite_dash_manifest_appends_token_to_media_attributes() {
+        let manifest = r#"<SegmentTemplate media="seg_$Number$.m4s" />"#;
+        let rewritten = rewrite_dash_manifest(manifest, "viewer1");
+        assert_eq!(
+            rewritten,
+            r#"<SegmentTemplate media="seg_$Number$.m4s?uk=viewer1" />"#
+        );
+    }
+
+    #[test]
+    fn rewrite_dash_manifest_leaves_unterminated_attribute_untouched() {
+        let manifest = r#"<SegmentTemplate media="seg_$Number$.m4s"#;
+        let rewritten = rewrite_dash_manifest(manifest, "viewer1");
+        assert_eq!(rewritten, manifest);
+    }
+
+    #[test]
+    fn parse_range_supports_start_end() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok((0, 99)));
+    }
+
+    #[test]
+    fn parse_range_supports_open_ended() {
+        assert_eq!(parse_range("bytes=900-", 1000), Ok((900, 999)));
+    }
+
+    #[test]
+    fn parse_range_supports_suffix() {
+        assert_eq!(parse_range("bytes=-500", 1000), Ok((500, 999)));
+        // A suffix longer than the resource just clamps to the whole thing.
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok((0, 999)));
+    }
+
+    #[test]
+    fn parse_range_rejects_start_past_end() {
+        assert_eq!(parse_range("bytes=1000-1001", 1000), Err(()));
+    }
+
+    #[test]
+    fn parse_range_rejects_malformed_and_empty_resource() {
+        assert_eq!(parse_range("not-a-range", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-99", 0), Err(()));
+    }
+
+    #[test]
+    fn content_encoding_negotiate_prefers_brotli_over_gzip() {
+        assert_eq!(ContentEncoding::negotiate("gzip, br, deflate"), Some(ContentEncoding::Brotli));
+    }
+
+    #[test]
+    fn content_encoding_negotiate_skips_q_zero() {
+        assert_eq!(ContentEncoding::negotiate("br;q=0, gzip"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::negotiate("br;q=0.0"), None);
+    }
+
+    #[test]
+    fn content_encoding_negotiate_none_when_nothing_supported() {
+        assert_eq!(ContentEncoding::negotiate("identity"), None);
     }
 }
\ No newline at end of file